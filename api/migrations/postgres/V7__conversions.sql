@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS conversions (
+	id TEXT,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+	user_id BIGINT,
+	goal TEXT,
+	visitor_id TEXT,
+	value DOUBLE PRECISION,
+	PRIMARY KEY (user_id, visitor_id, id, created_at)
+);
+
+SELECT create_hypertable('conversions', by_range('created_at'));
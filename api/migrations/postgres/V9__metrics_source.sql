@@ -0,0 +1 @@
+ALTER TABLE metrics ADD COLUMN IF NOT EXISTS source TEXT NOT NULL DEFAULT 'link';
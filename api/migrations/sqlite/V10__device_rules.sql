@@ -0,0 +1,6 @@
+CREATE TABLE device_rules (
+    key TEXT NOT NULL,
+    device TEXT NOT NULL,
+    url TEXT NOT NULL,
+    PRIMARY KEY (key, device)
+);
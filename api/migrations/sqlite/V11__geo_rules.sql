@@ -0,0 +1,11 @@
+CREATE TABLE geo_rules (
+    id INTEGER PRIMARY KEY,
+    key TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    continent TEXT,
+    country TEXT,
+    region TEXT,
+    url TEXT NOT NULL
+);
+
+CREATE INDEX geo_rules_key_idx ON geo_rules (key, position);
@@ -0,0 +1,9 @@
+CREATE TABLE url_variants (
+    id INTEGER PRIMARY KEY,
+    key TEXT NOT NULL,
+    name TEXT,
+    url TEXT NOT NULL,
+    weight INTEGER NOT NULL
+);
+
+CREATE INDEX url_variants_key_idx ON url_variants (key);
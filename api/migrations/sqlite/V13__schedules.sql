@@ -0,0 +1,13 @@
+ALTER TABLE urls ADD COLUMN active_from INTEGER;
+ALTER TABLE urls ADD COLUMN active_until INTEGER;
+
+CREATE TABLE url_schedules (
+    id INTEGER PRIMARY KEY,
+    key TEXT NOT NULL,
+    starts_at INTEGER,
+    ends_at INTEGER,
+    local_time INTEGER NOT NULL DEFAULT 0,
+    url TEXT NOT NULL
+);
+
+CREATE INDEX url_schedules_key_idx ON url_schedules (key);
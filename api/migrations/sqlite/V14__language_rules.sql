@@ -0,0 +1,6 @@
+CREATE TABLE language_rules (
+    key TEXT NOT NULL,
+    language TEXT NOT NULL,
+    url TEXT NOT NULL,
+    PRIMARY KEY (key, language)
+);
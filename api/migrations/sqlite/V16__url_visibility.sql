@@ -0,0 +1 @@
+ALTER TABLE urls ADD COLUMN visibility TEXT NOT NULL DEFAULT 'public';
@@ -0,0 +1,2 @@
+ALTER TABLE users ADD COLUMN display_name TEXT;
+ALTER TABLE users ADD COLUMN public_profile INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,11 @@
+ALTER TABLE urls ADD COLUMN og_title TEXT;
+ALTER TABLE urls ADD COLUMN og_description TEXT;
+ALTER TABLE urls ADD COLUMN og_image TEXT;
+
+CREATE TABLE url_metadata (
+    url TEXT PRIMARY KEY,
+    title TEXT,
+    description TEXT,
+    image TEXT,
+    fetched_at INTEGER NOT NULL
+);
@@ -0,0 +1,15 @@
+ALTER TABLE urls ADD COLUMN pixel_delay_ms INTEGER NOT NULL DEFAULT 500;
+
+CREATE TABLE url_pixels (
+    id INTEGER PRIMARY KEY,
+    key TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    url TEXT NOT NULL
+);
+
+CREATE INDEX url_pixels_key_idx ON url_pixels (key);
+
+CREATE TABLE pixel_hosts (
+    host TEXT PRIMARY KEY,
+    created_at INTEGER NOT NULL DEFAULT (unixepoch())
+);
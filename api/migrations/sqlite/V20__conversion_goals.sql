@@ -0,0 +1,8 @@
+CREATE TABLE conversion_goals (
+    token TEXT PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (unixepoch())
+);
+
+CREATE INDEX conversion_goals_user_idx ON conversion_goals (user_id);
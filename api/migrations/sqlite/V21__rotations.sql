@@ -0,0 +1,12 @@
+ALTER TABLE urls ADD COLUMN rotation TEXT;
+ALTER TABLE urls ADD COLUMN rotation_cursor INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE url_rotations (
+    id INTEGER PRIMARY KEY,
+    key TEXT NOT NULL,
+    url TEXT NOT NULL,
+    position INTEGER,
+    hits INTEGER NOT NULL DEFAULT 0,
+    last_served INTEGER,
+    UNIQUE (key, url)
+);
@@ -0,0 +1,15 @@
+CREATE TABLE mobile_apps (
+    id INTEGER PRIMARY KEY,
+    platform TEXT NOT NULL,
+    app_id TEXT NOT NULL,
+    store_url TEXT,
+    sha256_cert_fingerprints TEXT NOT NULL DEFAULT '',
+    UNIQUE (platform, app_id)
+);
+
+CREATE TABLE deep_links (
+    key TEXT PRIMARY KEY,
+    app_url TEXT NOT NULL,
+    ios_store_url TEXT,
+    android_store_url TEXT
+);
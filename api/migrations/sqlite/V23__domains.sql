@@ -0,0 +1,198 @@
+CREATE TABLE domains (
+    id INTEGER PRIMARY KEY,
+    hostname TEXT NOT NULL,
+    user_id INTEGER NOT NULL,
+    verification_token TEXT NOT NULL,
+    verified_at INTEGER,
+    not_found_url TEXT,
+    root_url TEXT,
+    created_at INTEGER NOT NULL DEFAULT (unixepoch()),
+    UNIQUE (hostname, user_id)
+);
+
+-- Anyone can claim a hostname, only the one who proves they own it gets to use it
+CREATE UNIQUE INDEX domains_verified_hostname_idx ON domains (hostname) WHERE verified_at IS NOT NULL;
+
+-- Links get an id of their own, so their keys only have to be unique within their domain. Ids are never handed out
+-- twice, so the history of an archived link can't end up on a new one.
+CREATE TABLE links (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    key TEXT NOT NULL,
+    domain_id INTEGER REFERENCES domains (id) ON DELETE RESTRICT,
+    url TEXT,
+    created_at INTEGER DEFAULT CURRENT_TIMESTAMP,
+    user_id INTEGER,
+    expires_at INTEGER,
+    max_clicks INTEGER,
+    clicks INTEGER NOT NULL DEFAULT 0,
+    exhausted_at INTEGER,
+    fallback_url TEXT,
+    deleted_at INTEGER,
+    revision_id INTEGER,
+    redirect_type TEXT NOT NULL DEFAULT '307',
+    forward_query INTEGER NOT NULL DEFAULT 0,
+    query_conflict TEXT NOT NULL DEFAULT 'incoming_wins',
+    forward_path INTEGER NOT NULL DEFAULT 0,
+    active_from INTEGER,
+    active_until INTEGER,
+    password_hash TEXT,
+    visibility TEXT NOT NULL DEFAULT 'public',
+    og_title TEXT,
+    og_description TEXT,
+    og_image TEXT,
+    pixel_delay_ms INTEGER NOT NULL DEFAULT 500,
+    rotation TEXT,
+    rotation_cursor INTEGER NOT NULL DEFAULT 0,
+    UNIQUE (domain_id, key)
+);
+
+INSERT INTO links (
+    id, key, url, created_at, user_id, expires_at, max_clicks, clicks, exhausted_at, fallback_url, deleted_at,
+    revision_id, redirect_type, forward_query, query_conflict, forward_path, active_from, active_until, password_hash,
+    visibility, og_title, og_description, og_image, pixel_delay_ms, rotation, rotation_cursor
+)
+SELECT
+    rowid, key, url, created_at, user_id, expires_at, max_clicks, clicks, exhausted_at, fallback_url, deleted_at,
+    revision_id, redirect_type, forward_query, query_conflict, forward_path, active_from, active_until, password_hash,
+    visibility, og_title, og_description, og_image, pixel_delay_ms, rotation, rotation_cursor
+FROM urls;
+
+-- The rule tables follow the link by its id instead of its key, rules of links that no longer exist are dropped
+CREATE TABLE device_rules_new (
+    url_id INTEGER NOT NULL REFERENCES urls (id) ON DELETE CASCADE,
+    device TEXT NOT NULL,
+    url TEXT NOT NULL,
+    PRIMARY KEY (url_id, device)
+);
+
+INSERT INTO device_rules_new (url_id, device, url)
+SELECT urls.rowid, device, device_rules.url FROM device_rules JOIN urls USING (key);
+
+CREATE TABLE geo_rules_new (
+    id INTEGER PRIMARY KEY,
+    url_id INTEGER NOT NULL REFERENCES urls (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    continent TEXT,
+    country TEXT,
+    region TEXT,
+    url TEXT NOT NULL
+);
+
+INSERT INTO geo_rules_new (id, url_id, position, continent, country, region, url)
+SELECT id, urls.rowid, position, continent, country, region, geo_rules.url FROM geo_rules JOIN urls USING (key);
+
+CREATE TABLE url_variants_new (
+    id INTEGER PRIMARY KEY,
+    url_id INTEGER NOT NULL REFERENCES urls (id) ON DELETE CASCADE,
+    name TEXT,
+    url TEXT NOT NULL,
+    weight INTEGER NOT NULL
+);
+
+INSERT INTO url_variants_new (id, url_id, name, url, weight)
+SELECT id, urls.rowid, name, url_variants.url, weight FROM url_variants JOIN urls USING (key);
+
+CREATE TABLE url_schedules_new (
+    id INTEGER PRIMARY KEY,
+    url_id INTEGER NOT NULL REFERENCES urls (id) ON DELETE CASCADE,
+    starts_at INTEGER,
+    ends_at INTEGER,
+    local_time INTEGER NOT NULL DEFAULT 0,
+    url TEXT NOT NULL
+);
+
+INSERT INTO url_schedules_new (id, url_id, starts_at, ends_at, local_time, url)
+SELECT id, urls.rowid, starts_at, ends_at, local_time, url_schedules.url FROM url_schedules JOIN urls USING (key);
+
+CREATE TABLE language_rules_new (
+    url_id INTEGER NOT NULL REFERENCES urls (id) ON DELETE CASCADE,
+    language TEXT NOT NULL,
+    url TEXT NOT NULL,
+    PRIMARY KEY (url_id, language)
+);
+
+INSERT INTO language_rules_new (url_id, language, url)
+SELECT urls.rowid, language, language_rules.url FROM language_rules JOIN urls USING (key);
+
+CREATE TABLE url_pixels_new (
+    id INTEGER PRIMARY KEY,
+    url_id INTEGER NOT NULL REFERENCES urls (id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    url TEXT NOT NULL
+);
+
+INSERT INTO url_pixels_new (id, url_id, kind, url)
+SELECT id, urls.rowid, kind, url_pixels.url FROM url_pixels JOIN urls USING (key);
+
+CREATE TABLE url_rotations_new (
+    id INTEGER PRIMARY KEY,
+    url_id INTEGER NOT NULL REFERENCES urls (id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    position INTEGER,
+    hits INTEGER NOT NULL DEFAULT 0,
+    last_served INTEGER,
+    UNIQUE (url_id, url)
+);
+
+INSERT INTO url_rotations_new (id, url_id, url, position, hits, last_served)
+SELECT id, urls.rowid, url_rotations.url, position, hits, last_served FROM url_rotations JOIN urls USING (key);
+
+CREATE TABLE deep_links_new (
+    url_id INTEGER PRIMARY KEY REFERENCES urls (id) ON DELETE CASCADE,
+    app_url TEXT NOT NULL,
+    ios_store_url TEXT,
+    android_store_url TEXT
+);
+
+INSERT INTO deep_links_new (url_id, app_url, ios_store_url, android_store_url)
+SELECT urls.rowid, app_url, ios_store_url, android_store_url FROM deep_links JOIN urls USING (key);
+
+-- Revisions outlive their link, archiving hands them over to the archived row
+CREATE TABLE url_revisions_new (
+    id INTEGER PRIMARY KEY,
+    url_id INTEGER REFERENCES urls (id) ON DELETE SET NULL,
+    archive_id INTEGER REFERENCES urls_archive (id),
+    url TEXT NOT NULL,
+    user_id INTEGER NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (unixepoch())
+);
+
+INSERT INTO url_revisions_new (id, url_id, url, user_id, created_at)
+SELECT id, urls.rowid, url_revisions.url, url_revisions.user_id, url_revisions.created_at
+FROM url_revisions JOIN urls USING (key);
+
+DROP TABLE device_rules;
+DROP TABLE geo_rules;
+DROP TABLE url_variants;
+DROP TABLE url_schedules;
+DROP TABLE language_rules;
+DROP TABLE url_pixels;
+DROP TABLE url_rotations;
+DROP TABLE deep_links;
+DROP TABLE url_revisions;
+DROP TABLE urls;
+
+ALTER TABLE links RENAME TO urls;
+ALTER TABLE device_rules_new RENAME TO device_rules;
+ALTER TABLE geo_rules_new RENAME TO geo_rules;
+ALTER TABLE url_variants_new RENAME TO url_variants;
+ALTER TABLE url_schedules_new RENAME TO url_schedules;
+ALTER TABLE language_rules_new RENAME TO language_rules;
+ALTER TABLE url_pixels_new RENAME TO url_pixels;
+ALTER TABLE url_rotations_new RENAME TO url_rotations;
+ALTER TABLE deep_links_new RENAME TO deep_links;
+ALTER TABLE url_revisions_new RENAME TO url_revisions;
+
+-- NULL never collides in `UNIQUE (domain_id, key)`, so keys of the shared domain need an index of their own
+CREATE UNIQUE INDEX urls_shared_key_idx ON urls (key) WHERE domain_id IS NULL;
+CREATE INDEX urls_user_id_idx ON urls (user_id);
+CREATE INDEX urls_domain_idx ON urls (domain_id);
+CREATE INDEX geo_rules_url_idx ON geo_rules (url_id, position);
+CREATE INDEX url_variants_url_idx ON url_variants (url_id);
+CREATE INDEX url_schedules_url_idx ON url_schedules (url_id);
+CREATE INDEX url_pixels_url_idx ON url_pixels (url_id);
+CREATE INDEX url_revisions_url_idx ON url_revisions (url_id);
+CREATE INDEX url_revisions_archive_idx ON url_revisions (archive_id);
+
+ALTER TABLE urls_archive ADD COLUMN url_id INTEGER;
+ALTER TABLE urls_archive ADD COLUMN domain_id INTEGER;
@@ -0,0 +1,14 @@
+ALTER TABLE domains ADD COLUMN not_found_template TEXT;
+ALTER TABLE domains ADD COLUMN expired_template TEXT;
+ALTER TABLE domains ADD COLUMN blocked_template TEXT;
+ALTER TABLE domains ADD COLUMN disabled_template TEXT;
+
+CREATE TABLE account_pages (
+    user_id INTEGER PRIMARY KEY,
+    not_found_url TEXT,
+    not_found_template TEXT,
+    root_url TEXT,
+    expired_template TEXT,
+    blocked_template TEXT,
+    disabled_template TEXT
+);
@@ -0,0 +1 @@
+ALTER TABLE urls ADD COLUMN require_signature INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,19 @@
+ALTER TABLE urls ADD COLUMN expires_at INTEGER;
+ALTER TABLE urls ADD COLUMN max_clicks INTEGER;
+ALTER TABLE urls ADD COLUMN clicks INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE urls ADD COLUMN exhausted_at INTEGER;
+ALTER TABLE urls ADD COLUMN fallback_url TEXT;
+
+CREATE TABLE urls_archive (
+    id INTEGER PRIMARY KEY,
+    key TEXT NOT NULL,
+    url TEXT,
+    user_id INTEGER,
+    created_at INTEGER,
+    expires_at INTEGER,
+    max_clicks INTEGER,
+    clicks INTEGER NOT NULL,
+    archived_at INTEGER NOT NULL
+);
+
+CREATE INDEX urls_archive_key_idx ON urls_archive (key);
@@ -0,0 +1,3 @@
+ALTER TABLE urls ADD COLUMN deleted_at INTEGER;
+
+CREATE INDEX urls_user_id_idx ON urls (user_id);
@@ -0,0 +1,16 @@
+CREATE TABLE url_revisions (
+    id INTEGER PRIMARY KEY,
+    key TEXT NOT NULL,
+    url TEXT NOT NULL,
+    user_id INTEGER NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (unixepoch())
+);
+
+CREATE INDEX url_revisions_key_idx ON url_revisions (key);
+
+ALTER TABLE urls ADD COLUMN revision_id INTEGER;
+
+INSERT INTO url_revisions (key, url, user_id, created_at)
+SELECT key, url, user_id, unixepoch(created_at) FROM urls;
+
+UPDATE urls SET revision_id = (SELECT id FROM url_revisions WHERE url_revisions.key = urls.key);
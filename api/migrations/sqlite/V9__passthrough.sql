@@ -0,0 +1,3 @@
+ALTER TABLE urls ADD COLUMN forward_query INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE urls ADD COLUMN query_conflict TEXT NOT NULL DEFAULT 'incoming_wins';
+ALTER TABLE urls ADD COLUMN forward_path INTEGER NOT NULL DEFAULT 0;
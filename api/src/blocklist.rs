@@ -0,0 +1,274 @@
+use std::{
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, PoisonError, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use tokio::time::interval;
+use url::{Host, Url};
+
+const DEFAULT_BLOCKLIST_PATH: &str = "./data/blocklist.txt";
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+pub type SharedBlocklist = Arc<RwLock<Blocklist>>;
+
+/// Entries are read line by line: empty lines and lines starting with `#` are ignored, entries without `/` or `*`
+/// block a domain including its subdomains and everything else is a url pattern where `*` matches any characters.
+pub struct Blocklist {
+    path: PathBuf,
+    lines: Vec<String>,
+    modified: Option<SystemTime>,
+}
+
+impl Blocklist {
+    pub fn from_env() -> Self {
+        let path = std::env::var("BLOCKLIST_PATH").unwrap_or(DEFAULT_BLOCKLIST_PATH.to_owned());
+        let mut blocklist = Blocklist {
+            path: PathBuf::from(path),
+            lines: Vec::new(),
+            modified: None,
+        };
+
+        if let Err(err) = blocklist.reload_if_changed() {
+            println!("Could not load blocklist {:?}: {:?}", blocklist.path, err);
+        }
+
+        blocklist
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &str> {
+        self.lines
+            .iter()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    }
+
+    /// Returns the first entry that blocks `url`.
+    pub fn find_match(&self, url: &str) -> Option<&str> {
+        let parsed = Url::parse(url).ok();
+        let host = parsed.as_ref().and_then(|url| url.host_str());
+        let url = url.to_ascii_lowercase();
+
+        self.entries().find(|entry| {
+            if entry.contains('/') || entry.contains('*') {
+                wildcard_match(&entry.to_ascii_lowercase(), &url)
+            } else {
+                host.is_some_and(|host| {
+                    host.eq_ignore_ascii_case(entry)
+                        || host.len() > entry.len()
+                            && host[host.len() - entry.len()..].eq_ignore_ascii_case(entry)
+                            && host.as_bytes()[host.len() - entry.len() - 1] == b'.'
+                })
+            }
+        })
+    }
+
+    pub fn reload_if_changed(&mut self) -> io::Result<bool> {
+        let modified = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata.modified().ok(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+
+        if modified.is_some() && modified == self.modified {
+            return Ok(false);
+        }
+
+        self.lines = match fs::read_to_string(&self.path) {
+            Ok(content) => content.lines().map(normalize_entry).collect(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        self.modified = modified;
+
+        Ok(true)
+    }
+
+    /// Adds an entry and writes it back to the blocklist file, returns false if it already existed.
+    pub fn add(&mut self, entry: &str) -> io::Result<bool> {
+        let entry = normalize_entry(entry);
+
+        if self.entries().any(|existing| existing == entry) {
+            return Ok(false);
+        }
+
+        self.lines.push(entry);
+        self.persist()?;
+
+        Ok(true)
+    }
+
+    /// Removes an entry and writes the blocklist file, returns false if there was nothing to remove.
+    pub fn remove(&mut self, entry: &str) -> io::Result<bool> {
+        let entry = normalize_entry(entry);
+        let count = self.lines.len();
+
+        self.lines.retain(|line| line.trim() != entry);
+
+        if self.lines.len() == count {
+            return Ok(false);
+        }
+
+        self.persist()?;
+
+        Ok(true)
+    }
+
+    fn persist(&mut self) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut content = self.lines.join("\n");
+        content.push('\n');
+
+        fs::write(&self.path, content)?;
+        self.modified = fs::metadata(&self.path)?.modified().ok();
+
+        Ok(())
+    }
+}
+
+/// Periodically picks up changes that were made to the blocklist file by hand.
+pub fn watch(blocklist: SharedBlocklist) {
+    tokio::spawn(async move {
+        let mut interval = interval(RELOAD_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let mut blocklist = blocklist.write().unwrap_or_else(PoisonError::into_inner);
+            match blocklist.reload_if_changed() {
+                Ok(true) => println!("Reloaded blocklist with {} entries", blocklist.entries().count()),
+                Ok(false) => {}
+                Err(err) => println!("Could not reload blocklist: {:?}", err),
+            }
+        }
+    });
+}
+
+/// Brings an entry into the form destinations are checked in, so an entry written with an IDN hostname still
+/// matches: domains become lowercase punycode and so does the host of url patterns, unless it contains a wildcard.
+fn normalize_entry(entry: &str) -> String {
+    let entry = entry.trim();
+
+    if entry.is_empty() || entry.starts_with('#') {
+        return entry.to_owned();
+    }
+
+    if !entry.contains('/') && !entry.contains('*') {
+        return match Host::parse(entry) {
+            Ok(host) => host.to_string(),
+            Err(_) => entry.to_ascii_lowercase(),
+        };
+    }
+
+    if let Some((scheme, rest)) = entry.split_once("://") {
+        let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+
+        if !host.contains('*')
+            && let Ok(host) = Host::parse(host)
+        {
+            return format!("{}://{host}{path}", scheme.to_ascii_lowercase());
+        }
+    }
+
+    entry.to_ascii_lowercase()
+}
+
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+
+    let Some(prefix) = parts.next() else {
+        return true;
+    };
+
+    let Some(mut rest) = value.strip_prefix(prefix) else {
+        return false;
+    };
+
+    let mut parts = parts.peekable();
+
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            return rest.ends_with(part);
+        }
+
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocklist(path: PathBuf, lines: &[&str]) -> Blocklist {
+        Blocklist {
+            path,
+            lines: lines.iter().map(|line| normalize_entry(line)).collect(),
+            modified: None,
+        }
+    }
+
+    #[test]
+    fn domains_block_their_subdomains() {
+        let blocklist = blocklist(PathBuf::new(), &["# scams", "", "Example.com"]);
+
+        assert_eq!(blocklist.find_match("https://example.com/login"), Some("example.com"));
+        assert_eq!(blocklist.find_match("https://www.EXAMPLE.com/"), Some("example.com"));
+        assert_eq!(blocklist.find_match("https://notexample.com/"), None);
+        assert_eq!(blocklist.find_match("https://example.com.evil.org/"), None);
+    }
+
+    #[test]
+    fn url_patterns_match_with_wildcards() {
+        let blocklist = blocklist(
+            PathBuf::new(),
+            &["https://example.org/ads/*", "*.exe", "https://*.example.net/"],
+        );
+
+        assert_eq!(
+            blocklist.find_match("https://example.org/ads/1"),
+            Some("https://example.org/ads/*")
+        );
+        assert_eq!(blocklist.find_match("https://example.org/news"), None);
+        assert_eq!(
+            blocklist.find_match("https://files.example.com/setup.EXE"),
+            Some("*.exe")
+        );
+        assert_eq!(
+            blocklist.find_match("https://cdn.example.net/"),
+            Some("https://*.example.net/")
+        );
+        assert_eq!(blocklist.find_match("https://cdn.example.net/app.js"), None);
+    }
+
+    #[test]
+    fn entries_are_stored_as_punycode() {
+        let path = std::env::temp_dir().join(format!("blocklist-{}.txt", std::process::id()));
+        let mut blocklist = blocklist(path.clone(), &[]);
+
+        assert!(blocklist.add(" Bücher.example ").unwrap());
+        assert!(blocklist.add("https://BÜCHER.example/*").unwrap());
+        assert!(!blocklist.add("xn--bcher-kva.example").unwrap());
+        assert_eq!(
+            blocklist.entries().collect::<Vec<_>>(),
+            ["xn--bcher-kva.example", "https://xn--bcher-kva.example/*"]
+        );
+        assert_eq!(
+            blocklist.find_match("https://shop.xn--bcher-kva.example/"),
+            Some("xn--bcher-kva.example")
+        );
+
+        assert!(blocklist.remove("bücher.example").unwrap());
+        assert!(!blocklist.remove("bücher.example").unwrap());
+
+        fs::remove_file(path).unwrap();
+    }
+}
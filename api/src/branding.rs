@@ -0,0 +1,257 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use rusqlite::{Connection, OptionalExtension, Row};
+
+use crate::{
+    domains,
+    entities::{BrandedPages, Domain},
+    pages::escape,
+    structs::UpdateBrandedPages,
+};
+
+pub const PAGE_COLUMNS: &str =
+    "not_found_url, not_found_template, root_url, expired_template, blocked_template, disabled_template";
+pub const MAX_TEMPLATE_LENGTH: usize = 64 * 1024;
+
+/// Templates are written by account owners but served from the short domains, the sandbox keeps scripts in them
+/// away from the visitor's cookies there.
+pub const TEMPLATE_POLICY: &str =
+    "sandbox; default-src 'none'; img-src * data:; style-src * 'unsafe-inline'; font-src * data:";
+
+// Changes made through the API reach the redirects after this long at most
+const CACHE_TTL: Duration = Duration::from_secs(30);
+// Hostnames come from the request, so the cache is bounded
+const MAX_CACHE_ENTRIES: usize = 10_000;
+
+pub fn pages_from_row(row: &Row) -> Result<BrandedPages, rusqlite::Error> {
+    Ok(BrandedPages {
+        not_found_url: row.get("not_found_url")?,
+        not_found_template: row.get("not_found_template")?,
+        root_url: row.get("root_url")?,
+        expired_template: row.get("expired_template")?,
+        blocked_template: row.get("blocked_template")?,
+        disabled_template: row.get("disabled_template")?,
+    })
+}
+
+pub fn find_account_pages(connection: &mut Connection, user_id: i64) -> Result<BrandedPages, rusqlite::Error> {
+    let mut query =
+        connection.prepare_cached(&format!("SELECT {PAGE_COLUMNS} FROM account_pages WHERE user_id = ?1"))?;
+
+    query
+        .query_row([user_id], pages_from_row)
+        .optional()
+        .map(Option::unwrap_or_default)
+}
+
+/// Empty strings clear a page, `None` leaves it as it is.
+pub fn update_account_pages(
+    connection: &mut Connection,
+    user_id: i64,
+    update: &UpdateBrandedPages,
+) -> Result<BrandedPages, rusqlite::Error> {
+    let transaction = connection.transaction()?;
+
+    transaction
+        .prepare_cached("INSERT INTO account_pages (user_id) VALUES (?1) ON CONFLICT (user_id) DO NOTHING")?
+        .execute([user_id])?;
+
+    let pages = transaction
+        .prepare_cached(&format!(
+            r"UPDATE account_pages SET
+                not_found_url = IIF(?2 IS NULL, not_found_url, NULLIF(?2, '')),
+                not_found_template = IIF(?3 IS NULL, not_found_template, NULLIF(?3, '')),
+                root_url = IIF(?4 IS NULL, root_url, NULLIF(?4, '')),
+                expired_template = IIF(?5 IS NULL, expired_template, NULLIF(?5, '')),
+                blocked_template = IIF(?6 IS NULL, blocked_template, NULLIF(?6, '')),
+                disabled_template = IIF(?7 IS NULL, disabled_template, NULLIF(?7, ''))
+              WHERE user_id = ?1
+              RETURNING {PAGE_COLUMNS}"
+        ))?
+        .query_row(
+            (
+                user_id,
+                &update.not_found_url,
+                &update.not_found_template,
+                &update.root_url,
+                &update.expired_template,
+                &update.blocked_template,
+                &update.disabled_template,
+            ),
+            pages_from_row,
+        )?;
+
+    transaction.commit()?;
+
+    Ok(pages)
+}
+
+/// Fills `{{key}}` and `{{domain}}` in a template.
+pub fn render(template: &str, key: &str, hostname: Option<&str>) -> String {
+    template
+        .replace("{{key}}", &escape(key))
+        .replace("{{domain}}", &escape(hostname.unwrap_or_default()))
+}
+
+/// Keeps the custom domains and pages the redirects need in memory, so requests for unknown keys and hosts don't
+/// cost more than the key lookup. Hosts that aren't a verified domain are cached as well.
+pub struct SiteCache {
+    domains: HashMap<String, (Instant, Option<Arc<Domain>>)>,
+    accounts: HashMap<i64, (Instant, Arc<BrandedPages>)>,
+}
+
+impl SiteCache {
+    pub fn new() -> Self {
+        SiteCache {
+            domains: HashMap::new(),
+            accounts: HashMap::new(),
+        }
+    }
+
+    pub fn domain(
+        &mut self,
+        connection: &mut Connection,
+        hostname: &str,
+    ) -> Result<Option<Arc<Domain>>, rusqlite::Error> {
+        if let Some((cached_at, domain)) = self.domains.get(hostname)
+            && cached_at.elapsed() < CACHE_TTL
+        {
+            return Ok(domain.clone());
+        }
+
+        let domain = domains::find_verified(connection, hostname)?.map(Arc::new);
+
+        sweep(&mut self.domains);
+        self.domains
+            .insert(hostname.to_owned(), (Instant::now(), domain.clone()));
+
+        Ok(domain)
+    }
+
+    fn account_pages(
+        &mut self,
+        connection: &mut Connection,
+        user_id: i64,
+    ) -> Result<Arc<BrandedPages>, rusqlite::Error> {
+        if let Some((cached_at, pages)) = self.accounts.get(&user_id)
+            && cached_at.elapsed() < CACHE_TTL
+        {
+            return Ok(pages.clone());
+        }
+
+        let pages = Arc::new(find_account_pages(connection, user_id)?);
+
+        sweep(&mut self.accounts);
+        self.accounts.insert(user_id, (Instant::now(), pages.clone()));
+
+        Ok(pages)
+    }
+
+    /// The pages for a request on `domain` about a link of `user_id`. The domain's own pages come first, then the
+    /// ones of the link's owner, or of the domain's owner for keys that don't exist.
+    pub fn pages(
+        &mut self,
+        connection: &mut Connection,
+        domain: Option<&Domain>,
+        user_id: Option<i64>,
+    ) -> Result<BrandedPages, rusqlite::Error> {
+        let account = match user_id.or(domain.map(|domain| domain.user_id)) {
+            Some(user_id) => (*self.account_pages(connection, user_id)?).clone(),
+            None => BrandedPages::default(),
+        };
+
+        Ok(match domain {
+            Some(domain) => domain.pages.clone().or(account),
+            None => account,
+        })
+    }
+}
+
+fn sweep<K: Eq + Hash, V>(entries: &mut HashMap<K, (Instant, V)>) {
+    if entries.len() < MAX_CACHE_ENTRIES {
+        return;
+    }
+
+    entries.retain(|_, (cached_at, _)| cached_at.elapsed() < CACHE_TTL);
+
+    if entries.len() >= MAX_CACHE_ENTRIES {
+        entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::sqlite;
+
+    fn update(pages: serde_json::Value) -> UpdateBrandedPages {
+        serde_json::from_value(pages).unwrap()
+    }
+
+    #[test]
+    fn domain_pages_come_before_account_pages() {
+        let mut connection = sqlite::create_test_connection();
+        let mut cache = SiteCache::new();
+        update_account_pages(
+            &mut connection,
+            1,
+            &update(json!({
+                "not_found_template": "<p>account {{key}}</p>",
+                "root_url": "https://example.com/account",
+                "expired_template": "<p>account expired</p>",
+            })),
+        )
+        .unwrap();
+        let domain = domains::create(&mut connection, 1, "go.example.com").unwrap();
+        let domain = domains::update(
+            &mut connection,
+            domain.id,
+            &update(json!({
+                "not_found_url": "https://example.com/domain",
+                "expired_template": "<p>domain expired</p>",
+            })),
+        )
+        .unwrap();
+
+        let pages = cache.pages(&mut connection, Some(&domain), Some(1)).unwrap();
+        assert_eq!(pages.not_found_url.as_deref(), Some("https://example.com/domain"));
+        assert_eq!(pages.not_found_template, None);
+        assert_eq!(pages.root_url.as_deref(), Some("https://example.com/account"));
+        assert_eq!(pages.expired_template.as_deref(), Some("<p>domain expired</p>"));
+        assert_eq!(pages.blocked_template, None);
+    }
+
+    #[test]
+    fn unknown_keys_get_the_pages_of_the_domain_owner() {
+        let mut connection = sqlite::create_test_connection();
+        let mut cache = SiteCache::new();
+        update_account_pages(&mut connection, 1, &update(json!({ "not_found_template": "owner" }))).unwrap();
+        update_account_pages(&mut connection, 2, &update(json!({ "not_found_template": "visitor" }))).unwrap();
+        let domain = domains::create(&mut connection, 1, "go.example.com").unwrap();
+
+        let pages = cache.pages(&mut connection, Some(&domain), None).unwrap();
+        assert_eq!(pages.not_found_template.as_deref(), Some("owner"));
+
+        let pages = cache.pages(&mut connection, None, Some(2)).unwrap();
+        assert_eq!(pages.not_found_template.as_deref(), Some("visitor"));
+
+        let pages = cache.pages(&mut connection, None, None).unwrap();
+        assert_eq!(pages.not_found_template, None);
+    }
+
+    #[test]
+    fn templates_get_the_key_and_domain_escaped() {
+        assert_eq!(
+            render("<p>{{key}} on {{domain}}</p>", "<b>", Some("go.example.com")),
+            "<p>&lt;b&gt; on go.example.com</p>"
+        );
+        assert_eq!(render("{{domain}}", "key", None), "");
+    }
+}
@@ -0,0 +1,30 @@
+use axum::http::HeaderMap;
+
+use crate::{entities::Device, headers::TypedHeaderValues};
+
+/// Detects the visitor's platform from the CloudFront viewer headers and falls back to the User-Agent if
+/// the request didn't pass through CloudFront.
+pub fn detect(headers: &HeaderMap) -> Option<Device> {
+    match (
+        headers.bool("cloudfront-is-ios-viewer"),
+        headers.bool("cloudfront-is-android-viewer"),
+    ) {
+        (Some(true), _) => return Some(Device::Ios),
+        (_, Some(true)) => return Some(Device::Android),
+        (Some(false), Some(false)) => return None,
+        _ => {}
+    }
+
+    let user_agent = headers.string("user-agent")?;
+
+    if ["iPhone", "iPad", "iPod"]
+        .iter()
+        .any(|device| user_agent.contains(device))
+    {
+        Some(Device::Ios)
+    } else if user_agent.contains("Android") {
+        Some(Device::Android)
+    } else {
+        None
+    }
+}
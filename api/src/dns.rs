@@ -0,0 +1,180 @@
+use std::{fmt, path::PathBuf};
+
+use serde::Deserialize;
+
+const DEFAULT_RESOLVER_URL: &str = "https://cloudflare-dns.com/dns-query";
+const TXT_RECORD_TYPE: u16 = 16;
+
+/// Looks up TXT records for domain verification.
+///
+/// By default DNS is queried over HTTPS through the JSON API that Cloudflare and Google offer, `DNS_RESOLVER_URL`
+/// points it at another resolver. Setting `DNS_TXT_RECORDS_PATH` replaces DNS with a local file of `name value`
+/// lines, which stands in for a resolver in development and tests. The file is read on every lookup, so records
+/// can be added while the server is running.
+#[derive(Clone)]
+pub enum TxtResolver {
+    Https { client: reqwest::Client, url: String },
+    File(PathBuf),
+}
+
+#[derive(Debug)]
+pub enum LookupError {
+    Request(reqwest::Error),
+    File(std::io::Error),
+    Response,
+}
+
+#[derive(Deserialize)]
+struct DnsResponse {
+    #[serde(rename = "Status")]
+    status: u16,
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DnsAnswer>,
+}
+
+#[derive(Deserialize)]
+struct DnsAnswer {
+    #[serde(rename = "type")]
+    record_type: u16,
+    data: String,
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LookupError::Request(err) => write!(f, "DNS request failed: {err}"),
+            LookupError::File(err) => write!(f, "DNS records file can't be read: {err}"),
+            LookupError::Response => write!(f, "DNS resolver sent an invalid response"),
+        }
+    }
+}
+
+impl TxtResolver {
+    pub fn from_env() -> Self {
+        if let Ok(path) = std::env::var("DNS_TXT_RECORDS_PATH") {
+            return TxtResolver::File(PathBuf::from(path));
+        }
+
+        TxtResolver::Https {
+            client: reqwest::Client::new(),
+            url: std::env::var("DNS_RESOLVER_URL").unwrap_or(DEFAULT_RESOLVER_URL.to_owned()),
+        }
+    }
+
+    pub async fn lookup(&self, name: &str) -> Result<Vec<String>, LookupError> {
+        match self {
+            TxtResolver::Https { client, url } => {
+                let body = client
+                    .get(url)
+                    .query(&[("name", name), ("type", "TXT")])
+                    .header(reqwest::header::ACCEPT, "application/dns-json")
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(LookupError::Request)?
+                    .bytes()
+                    .await
+                    .map_err(LookupError::Request)?;
+
+                let response: DnsResponse = serde_json::from_slice(&body).map_err(|_| LookupError::Response)?;
+
+                // NXDOMAIN only means the record hasn't been created yet
+                if response.status != 0 && response.status != 3 {
+                    return Err(LookupError::Response);
+                }
+
+                Ok(response
+                    .answer
+                    .iter()
+                    .filter(|answer| answer.record_type == TXT_RECORD_TYPE)
+                    .map(|answer| unquote(&answer.data))
+                    .collect())
+            }
+            TxtResolver::File(path) => {
+                let records = std::fs::read_to_string(path).map_err(LookupError::File)?;
+
+                Ok(records
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .filter_map(|line| line.split_once(char::is_whitespace))
+                    .filter(|(record, _)| record.trim_end_matches('.').eq_ignore_ascii_case(name))
+                    .map(|(_, value)| unquote(value.trim()))
+                    .collect())
+            }
+        }
+    }
+}
+
+/// TXT data comes as one or more quoted strings that together make up the value.
+fn unquote(data: &str) -> String {
+    if !data.starts_with('"') {
+        return data.to_owned();
+    }
+
+    data.split('"').skip(1).step_by(2).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::domains;
+
+    fn resolver(name: &str, records: &str) -> (TxtResolver, PathBuf) {
+        let path = std::env::temp_dir().join(format!("txt-records-{name}-{}.txt", std::process::id()));
+        fs::write(&path, records).unwrap();
+
+        (TxtResolver::File(path.clone()), path)
+    }
+
+    #[tokio::test]
+    async fn matching_records_verify_the_domain() {
+        let (resolver, path) = resolver(
+            "match",
+            "# verification records\n\
+             _url-shortener.go.example.com. \"url-shortener-verification=\" \"abc123\"\n\
+             _url-shortener.other.example.com url-shortener-verification=other\n",
+        );
+        let name = domains::verification_record("go.example.com");
+
+        let records = resolver.lookup(&name).await.unwrap();
+        assert_eq!(records, [domains::verification_value("abc123")]);
+        assert!(domains::is_verified_by(&records, "abc123"));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn records_with_another_token_do_not_verify_the_domain() {
+        let (resolver, path) = resolver(
+            "mismatch",
+            "_url-shortener.go.example.com url-shortener-verification=abc123\n\
+             _url-shortener.go.example.com v=spf1 -all\n",
+        );
+        let name = domains::verification_record("go.example.com");
+
+        let records = resolver.lookup(&name).await.unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(!domains::is_verified_by(&records, "xyz789"));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_records_do_not_verify_the_domain() {
+        let (resolver, path) = resolver(
+            "missing",
+            "_url-shortener.other.example.com url-shortener-verification=abc123\n",
+        );
+        let name = domains::verification_record("go.example.com");
+
+        let records = resolver.lookup(&name).await.unwrap();
+        assert!(records.is_empty());
+        assert!(!domains::is_verified_by(&records, "abc123"));
+
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(resolver.lookup(&name).await, Err(LookupError::File(_))));
+    }
+}
@@ -0,0 +1,247 @@
+use axum::http::HeaderMap;
+use rand::{distributions::Alphanumeric, Rng};
+use rusqlite::{Connection, OptionalExtension, Row};
+use url::Host;
+
+use crate::{branding, entities::Domain, headers::TypedHeaderValues, links::timestamp, structs::UpdateBrandedPages};
+
+const TOKEN_LENGTH: usize = 32;
+const VERIFICATION_RECORD: &str = "_url-shortener";
+const VERIFICATION_PREFIX: &str = "url-shortener-verification=";
+
+const DOMAIN_COLUMNS: &str = r"id, hostname, user_id, verification_token, verified_at, not_found_url, not_found_template,
+    root_url, expired_template, blocked_template, disabled_template, created_at";
+
+fn domain_from_row(row: &Row) -> Result<Domain, rusqlite::Error> {
+    let verified_at: Option<i64> = row.get("verified_at")?;
+    let created_at: i64 = row.get("created_at")?;
+
+    Ok(Domain {
+        id: row.get("id")?,
+        hostname: row.get("hostname")?,
+        user_id: row.get("user_id")?,
+        verification_token: row.get("verification_token")?,
+        verified_at: verified_at.map(timestamp),
+        pages: branding::pages_from_row(row)?,
+        created_at: timestamp(created_at),
+    })
+}
+
+/// Lowercases `hostname` and checks that it is a domain name, IP addresses and ports are refused.
+pub fn normalize_hostname(hostname: &str) -> Option<String> {
+    let hostname = hostname.trim().trim_end_matches('.').to_ascii_lowercase();
+
+    match Host::parse(&hostname) {
+        Ok(Host::Domain(domain)) if domain.contains('.') => Some(domain),
+        _ => None,
+    }
+}
+
+/// The hostname the request was sent to, without the port.
+pub fn request_hostname(headers: &HeaderMap) -> Option<String> {
+    let host = headers.string("host")?;
+    let hostname = host
+        .rsplit_once(':')
+        .filter(|(_, port)| port.chars().all(|c| c.is_ascii_digit()))
+        .map_or(host.as_str(), |(hostname, _)| hostname);
+
+    Some(hostname.trim_end_matches('.').to_ascii_lowercase())
+}
+
+/// Name of the TXT record that proves control over `hostname`.
+pub fn verification_record(hostname: &str) -> String {
+    format!("{VERIFICATION_RECORD}.{hostname}")
+}
+
+pub fn verification_value(token: &str) -> String {
+    format!("{VERIFICATION_PREFIX}{token}")
+}
+
+/// Whether one of the TXT `records` found at the verification record carries the value for `token`.
+pub fn is_verified_by(records: &[String], token: &str) -> bool {
+    let expected = verification_value(token);
+
+    records.iter().any(|record| record.trim() == expected)
+}
+
+/// The domain serving requests for `hostname`, unverified claims are ignored.
+pub fn find_verified(connection: &mut Connection, hostname: &str) -> Result<Option<Domain>, rusqlite::Error> {
+    let mut query = connection.prepare_cached(&format!(
+        "SELECT {DOMAIN_COLUMNS} FROM domains WHERE hostname = ?1 AND verified_at IS NOT NULL"
+    ))?;
+
+    query.query_row([hostname], domain_from_row).optional()
+}
+
+pub fn find_owned(
+    connection: &mut Connection,
+    user_id: i64,
+    hostname: &str,
+) -> Result<Option<Domain>, rusqlite::Error> {
+    let mut query = connection.prepare_cached(&format!(
+        "SELECT {DOMAIN_COLUMNS} FROM domains WHERE user_id = ?1 AND hostname = ?2"
+    ))?;
+
+    query.query_row((user_id, hostname), domain_from_row).optional()
+}
+
+pub fn find_by_user(connection: &mut Connection, user_id: i64) -> Result<Vec<Domain>, rusqlite::Error> {
+    let mut query = connection.prepare_cached(&format!(
+        "SELECT {DOMAIN_COLUMNS} FROM domains WHERE user_id = ?1 ORDER BY hostname"
+    ))?;
+
+    let domains = query
+        .query_map([user_id], domain_from_row)?
+        .collect::<Result<Vec<Domain>, _>>()?;
+
+    Ok(domains)
+}
+
+pub fn create(connection: &mut Connection, user_id: i64, hostname: &str) -> Result<Domain, rusqlite::Error> {
+    let token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect();
+
+    let mut query = connection.prepare_cached(&format!(
+        "INSERT INTO domains (hostname, user_id, verification_token) VALUES (?1, ?2, ?3) RETURNING {DOMAIN_COLUMNS}"
+    ))?;
+
+    query.query_row((hostname, user_id, token), domain_from_row)
+}
+
+/// Empty strings clear a page, `None` leaves it as it is.
+pub fn update(connection: &mut Connection, id: i64, update: &UpdateBrandedPages) -> Result<Domain, rusqlite::Error> {
+    let mut query = connection.prepare_cached(&format!(
+        r"UPDATE domains SET
+            not_found_url = IIF(?2 IS NULL, not_found_url, NULLIF(?2, '')),
+            not_found_template = IIF(?3 IS NULL, not_found_template, NULLIF(?3, '')),
+            root_url = IIF(?4 IS NULL, root_url, NULLIF(?4, '')),
+            expired_template = IIF(?5 IS NULL, expired_template, NULLIF(?5, '')),
+            blocked_template = IIF(?6 IS NULL, blocked_template, NULLIF(?6, '')),
+            disabled_template = IIF(?7 IS NULL, disabled_template, NULLIF(?7, ''))
+          WHERE id = ?1
+          RETURNING {DOMAIN_COLUMNS}"
+    ))?;
+
+    query.query_row(
+        (
+            id,
+            &update.not_found_url,
+            &update.not_found_template,
+            &update.root_url,
+            &update.expired_template,
+            &update.blocked_template,
+            &update.disabled_template,
+        ),
+        domain_from_row,
+    )
+}
+
+/// Fails with a constraint violation when another account verified the hostname first.
+pub fn mark_verified(connection: &mut Connection, id: i64) -> Result<Domain, rusqlite::Error> {
+    let mut query = connection.prepare_cached(&format!(
+        "UPDATE domains SET verified_at = IFNULL(verified_at, unixepoch()) WHERE id = ?1 RETURNING {DOMAIN_COLUMNS}"
+    ))?;
+
+    query.query_row([id], domain_from_row)
+}
+
+/// Deleted links count as well, they keep their key reserved on the domain.
+pub fn has_links(connection: &mut Connection, id: i64) -> Result<bool, rusqlite::Error> {
+    connection
+        .prepare_cached("SELECT EXISTS (SELECT 1 FROM urls WHERE domain_id = ?1)")?
+        .query_row([id], |row| row.get(0))
+}
+
+pub fn delete(connection: &mut Connection, id: i64) -> Result<bool, rusqlite::Error> {
+    connection
+        .prepare_cached("DELETE FROM domains WHERE id = ?1")?
+        .execute([id])
+        .map(|deleted| deleted > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        links,
+        routes::{api::api, auth::auth::StoreError},
+        sqlite,
+        structs::CreateShortUrl,
+    };
+
+    fn payload(url: &str) -> CreateShortUrl {
+        serde_json::from_value(json!({ "url": url })).unwrap()
+    }
+
+    #[test]
+    fn keys_are_unique_per_domain() {
+        let mut connection = sqlite::create_test_connection();
+        let domain = create(&mut connection, 1, "go.example.com").unwrap();
+        let domain = mark_verified(&mut connection, domain.id).unwrap();
+
+        api::create_short_url(
+            &mut connection,
+            1,
+            "launch",
+            None,
+            &payload("https://example.com/shared"),
+        )
+        .unwrap();
+        api::create_short_url(
+            &mut connection,
+            1,
+            "launch",
+            Some(domain.id),
+            &payload("https://example.com/own"),
+        )
+        .unwrap();
+
+        for domain_id in [None, Some(domain.id)] {
+            let err = api::create_short_url(&mut connection, 1, "launch", domain_id, &payload("https://example.com"))
+                .unwrap_err();
+            assert!(matches!(err, StoreError::Database(err) if api::is_unique_violation(&err)));
+        }
+
+        let shared = links::find_link(&mut connection, None, "launch").unwrap().unwrap();
+        let own = links::find_link(&mut connection, Some(domain.id), "launch")
+            .unwrap()
+            .unwrap();
+        assert_eq!(shared.url, "https://example.com/shared");
+        assert_eq!(shared.domain, None);
+        assert_eq!(own.url, "https://example.com/own");
+        assert_eq!(own.key, "launch");
+        assert_eq!(own.domain.as_deref(), Some("go.example.com"));
+        assert_ne!(shared.address(), own.address());
+    }
+
+    #[test]
+    fn deleted_links_keep_their_domain() {
+        let mut connection = sqlite::create_test_connection();
+        let domain = create(&mut connection, 1, "go.example.com").unwrap();
+        api::create_short_url(
+            &mut connection,
+            1,
+            "gone",
+            Some(domain.id),
+            &payload("https://example.com"),
+        )
+        .unwrap();
+        let link = links::find_link(&mut connection, Some(domain.id), "gone")
+            .unwrap()
+            .unwrap();
+
+        api::delete_link(&mut connection, link.id).unwrap();
+        assert!(has_links(&mut connection, domain.id).unwrap());
+        assert!(delete(&mut connection, domain.id).is_err());
+
+        let remaining: i64 = connection
+            .query_row("SELECT count(*) FROM urls WHERE key = 'gone'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(remaining, 1);
+    }
+}
@@ -1,10 +1,441 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize, Serializer};
 use time::OffsetDateTime;
 
 #[derive(Debug, Clone)]
 pub struct User {
     pub id: i64,
     pub email: String,
+    pub is_admin: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Link {
+    #[serde(skip)]
+    pub id: i64,
+    pub key: String,
+    /// Hostname of the custom domain the link is on, `None` for the shared domain.
+    pub domain: Option<String>,
+    pub url: String,
+    #[serde(skip)]
+    pub user_id: i64,
+    #[serde(with = "time::serde::timestamp::milliseconds")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::timestamp::milliseconds::option")]
+    pub expires_at: Option<OffsetDateTime>,
+    pub max_clicks: Option<i64>,
+    pub clicks: i64,
+    pub fallback_url: Option<String>,
+    pub revision_id: Option<i64>,
+    pub redirect_type: RedirectType,
+    pub forward_query: bool,
+    pub query_conflict: QueryConflict,
+    pub forward_path: bool,
+    #[serde(with = "time::serde::timestamp::milliseconds::option")]
+    pub active_from: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::timestamp::milliseconds::option")]
+    pub active_until: Option<OffsetDateTime>,
+    #[serde(rename = "password_protected", serialize_with = "serialize_is_some")]
+    pub password_hash: Option<String>,
+    pub visibility: Visibility,
+    /// Unsigned urls of the link are refused, it can only be shared through signed ones.
+    pub require_signature: bool,
+    pub og_title: Option<String>,
+    pub og_description: Option<String>,
+    pub og_image: Option<String>,
+    pub pixel_delay_ms: u32,
+    pub rotation: Option<RotationMode>,
+}
+
+// Only tells whether a secret is set without exposing it
+fn serialize_is_some<S: Serializer, T>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bool(value.is_some())
+}
+
+impl Link {
+    /// Tells links apart across domains, keys alone are only unique within one. Signatures are made for it.
+    pub fn address(&self) -> String {
+        match &self.domain {
+            Some(hostname) => format!("{hostname}/{}", self.key),
+            None => self.key.clone(),
+        }
+    }
+
+    pub fn is_expired(&self, now: OffsetDateTime) -> bool {
+        let expired = self.expires_at.is_some_and(|expires_at| expires_at <= now);
+        let exhausted = self.max_clicks.is_some_and(|max_clicks| self.clicks >= max_clicks);
+
+        expired || exhausted || self.is_deactivated(now)
+    }
+
+    /// Links past the end of their active window count as expired, but are shown as disabled.
+    pub fn is_deactivated(&self, now: OffsetDateTime) -> bool {
+        self.active_until.is_some_and(|active_until| active_until <= now)
+    }
+
+    /// Links with an activation date in the future behave as if they didn't exist yet.
+    pub fn is_pending(&self, now: OffsetDateTime) -> bool {
+        self.active_from.is_some_and(|active_from| now < active_from)
+    }
+
+    /// Whether every visit ends up at the same place, only then may a permanent redirect be cached. Rule tables are
+    /// not part of the link and have to be checked separately.
+    pub fn is_static(&self) -> bool {
+        self.expires_at.is_none()
+            && self.max_clicks.is_none()
+            && self.active_until.is_none()
+            && !self.forward_query
+            && !self.forward_path
+            && self.rotation.is_none()
+            && !self.require_signature
+            && !self.is_restricted()
+    }
+
+    /// Restricted links must not be cached, otherwise the browser would skip the access check on the next visit.
+    pub fn is_restricted(&self) -> bool {
+        self.visibility == Visibility::Private || self.password_hash.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum RedirectType {
+    #[serde(rename = "301")]
+    MovedPermanently,
+    #[serde(rename = "302")]
+    Found,
+    #[default]
+    #[serde(rename = "307")]
+    TemporaryRedirect,
+    #[serde(rename = "308")]
+    PermanentRedirect,
+    #[serde(rename = "meta_refresh")]
+    MetaRefresh,
+}
+
+impl RedirectType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RedirectType::MovedPermanently => "301",
+            RedirectType::Found => "302",
+            RedirectType::TemporaryRedirect => "307",
+            RedirectType::PermanentRedirect => "308",
+            RedirectType::MetaRefresh => "meta_refresh",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "301" => Some(RedirectType::MovedPermanently),
+            "302" => Some(RedirectType::Found),
+            "307" => Some(RedirectType::TemporaryRedirect),
+            "308" => Some(RedirectType::PermanentRedirect),
+            "meta_refresh" => Some(RedirectType::MetaRefresh),
+            _ => None,
+        }
+    }
+
+    /// Browsers cache permanent redirects, so repeated visits never reach us and are missing from the metrics.
+    pub fn is_permanent(&self) -> bool {
+        matches!(self, RedirectType::MovedPermanently | RedirectType::PermanentRedirect)
+    }
+}
+
+/// Decides which value wins when a forwarded query parameter is also part of the destination url.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryConflict {
+    #[default]
+    IncomingWins,
+    DestinationWins,
+    KeepBoth,
+}
+
+impl QueryConflict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QueryConflict::IncomingWins => "incoming_wins",
+            QueryConflict::DestinationWins => "destination_wins",
+            QueryConflict::KeepBoth => "keep_both",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "incoming_wins" => Some(QueryConflict::IncomingWins),
+            "destination_wins" => Some(QueryConflict::DestinationWins),
+            "keep_both" => Some(QueryConflict::KeepBoth),
+            _ => None,
+        }
+    }
+}
+
+/// Private links only resolve for their owner, who has to be logged in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    #[default]
+    Public,
+    Private,
+}
+
+impl Visibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Private => "private",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "public" => Some(Visibility::Public),
+            "private" => Some(Visibility::Private),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Device {
+    Ios,
+    Android,
+}
+
+impl Device {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Device::Ios => "ios",
+            Device::Android => "android",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "ios" => Some(Device::Ios),
+            "android" => Some(Device::Android),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceRule {
+    pub device: Device,
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GeoRule {
+    #[serde(default, skip_deserializing)]
+    pub id: i64,
+    pub continent: Option<String>,
+    pub country: Option<String>,
+    pub region: Option<String>,
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LanguageRule {
+    pub language: String,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PixelKind {
+    Image,
+    Script,
+}
+
+impl PixelKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PixelKind::Image => "image",
+            PixelKind::Script => "script",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "image" => Some(PixelKind::Image),
+            "script" => Some(PixelKind::Script),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Pixel {
+    pub kind: PixelKind,
+    pub url: String,
+}
+
+/// A custom short domain. Links can only be created on it once the owner proved control over its DNS.
+#[derive(Debug, Clone, Serialize)]
+pub struct Domain {
+    #[serde(skip)]
+    pub id: i64,
+    pub hostname: String,
+    #[serde(skip)]
+    pub user_id: i64,
+    pub verification_token: String,
+    #[serde(with = "time::serde::timestamp::milliseconds::option")]
+    pub verified_at: Option<OffsetDateTime>,
+    #[serde(flatten)]
+    pub pages: BrandedPages,
+    #[serde(with = "time::serde::timestamp::milliseconds")]
+    pub created_at: OffsetDateTime,
+}
+
+/// Pages shown instead of the bare error statuses, configured per account and per domain.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BrandedPages {
+    pub not_found_url: Option<String>,
+    pub not_found_template: Option<String>,
+    pub root_url: Option<String>,
+    pub expired_template: Option<String>,
+    pub blocked_template: Option<String>,
+    pub disabled_template: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PageKind {
+    NotFound,
+    Expired,
+    Blocked,
+    Disabled,
+}
+
+impl BrandedPages {
+    pub fn template(&self, kind: PageKind) -> Option<&str> {
+        match kind {
+            PageKind::NotFound => self.not_found_template.as_deref(),
+            PageKind::Expired => self.expired_template.as_deref(),
+            PageKind::Blocked => self.blocked_template.as_deref(),
+            PageKind::Disabled => self.disabled_template.as_deref(),
+        }
+    }
+
+    /// Fills what `self` leaves open from `other`. The not-found URL and template are taken together, so a domain's
+    /// not-found URL isn't shadowed by its account's template.
+    pub fn or(self, other: BrandedPages) -> BrandedPages {
+        let (not_found_url, not_found_template) = if self.not_found_url.is_some() || self.not_found_template.is_some() {
+            (self.not_found_url, self.not_found_template)
+        } else {
+            (other.not_found_url, other.not_found_template)
+        };
+
+        BrandedPages {
+            not_found_url,
+            not_found_template,
+            root_url: self.root_url.or(other.root_url),
+            expired_template: self.expired_template.or(other.expired_template),
+            blocked_template: self.blocked_template.or(other.blocked_template),
+            disabled_template: self.disabled_template.or(other.disabled_template),
+        }
+    }
+}
+
+/// Where a visit came from, QR codes point at their own route so scans can be counted apart from clicks.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClickSource {
+    Link,
+    Qr,
+}
+
+impl ClickSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClickSource::Link => "link",
+            ClickSource::Qr => "qr",
+        }
+    }
+}
+
+/// How a rotating link walks through its destinations, unlike A/B variants every click moves the rotation on.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RotationMode {
+    RoundRobin,
+    LeastRecentlyServed,
+}
+
+impl RotationMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RotationMode::RoundRobin => "round_robin",
+            RotationMode::LeastRecentlyServed => "least_recently_served",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "round_robin" => Some(RotationMode::RoundRobin),
+            "least_recently_served" => Some(RotationMode::LeastRecentlyServed),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RotationDestination {
+    #[serde(default, skip_deserializing)]
+    pub id: i64,
+    pub url: String,
+    #[serde(default, skip_deserializing)]
+    pub hits: i64,
+}
+
+/// An app that may open links of the short domain, `app_id` is `TEAMID.bundle.id` on iOS and the package name on
+/// Android.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MobileApp {
+    pub platform: Device,
+    pub app_id: String,
+    pub store_url: Option<String>,
+    #[serde(default)]
+    pub sha256_cert_fingerprints: Vec<String>,
+}
+
+/// Opens `app_url` (a custom scheme or universal link) on mobile devices, the store urls default to the ones of the
+/// configured apps.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeepLink {
+    pub app_url: String,
+    pub ios_store_url: Option<String>,
+    pub android_store_url: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UrlVariant {
+    #[serde(default, skip_deserializing)]
+    pub id: i64,
+    pub name: Option<String>,
+    pub url: String,
+    pub weight: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScheduleWindow {
+    #[serde(default, skip_deserializing)]
+    pub id: i64,
+    #[serde(default, with = "time::serde::timestamp::milliseconds::option")]
+    pub starts_at: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::timestamp::milliseconds::option")]
+    pub ends_at: Option<OffsetDateTime>,
+    #[serde(default)]
+    pub local_time: bool,
+    pub url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UrlRevision {
+    pub id: i64,
+    pub url: String,
+    pub user_id: i64,
+    #[serde(with = "time::serde::timestamp::milliseconds")]
+    pub created_at: OffsetDateTime,
 }
 
 #[derive(Debug, Serialize)]
@@ -14,3 +445,37 @@ pub struct MetricsWithinInterval {
     pub count: i64,
     pub unique_count: i64,
 }
+
+#[derive(Debug, Serialize)]
+pub struct VariantMetrics {
+    pub variant_id: Option<i64>,
+    pub name: Option<String>,
+    pub url: Option<String>,
+    pub count: i64,
+    pub unique_count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LanguageMetrics {
+    pub language: Option<String>,
+    pub count: i64,
+    pub unique_count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConversionGoal {
+    pub token: String,
+    pub name: String,
+    #[serde(with = "time::serde::timestamp::milliseconds")]
+    pub created_at: OffsetDateTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConversionMetrics {
+    pub key: String,
+    pub domain: Option<String>,
+    pub clicks: i64,
+    pub conversions: i64,
+    pub conversion_rate: f64,
+    pub conversion_value: f64,
+}
@@ -0,0 +1,161 @@
+use axum::http::HeaderMap;
+
+use crate::{entities::GeoRule, headers::TypedHeaderValues};
+
+pub const CONTINENTS: &[&str] = &["AF", "AN", "AS", "EU", "NA", "OC", "SA"];
+
+// ISO 3166-1 alpha-2 codes per continent, CloudFront only tells us the country
+const AFRICA: &[&str] = &[
+    "AO", "BF", "BI", "BJ", "BW", "CD", "CF", "CG", "CI", "CM", "CV", "DJ", "DZ", "EG", "EH", "ER", "ET", "GA", "GH",
+    "GM", "GN", "GQ", "GW", "KE", "KM", "LR", "LS", "LY", "MA", "MG", "ML", "MR", "MU", "MW", "MZ", "NA", "NE", "NG",
+    "RE", "RW", "SC", "SD", "SH", "SL", "SN", "SO", "SS", "ST", "SZ", "TD", "TG", "TN", "TZ", "UG", "YT", "ZA", "ZM",
+    "ZW",
+];
+const ANTARCTICA: &[&str] = &["AQ", "BV", "GS", "HM", "TF"];
+const ASIA: &[&str] = &[
+    "AE", "AF", "AM", "AZ", "BD", "BH", "BN", "BT", "CC", "CN", "CX", "CY", "GE", "HK", "ID", "IL", "IN", "IO", "IQ",
+    "IR", "JO", "JP", "KG", "KH", "KP", "KR", "KW", "KZ", "LA", "LB", "LK", "MM", "MN", "MO", "MV", "MY", "NP", "OM",
+    "PH", "PK", "PS", "QA", "SA", "SG", "SY", "TH", "TJ", "TL", "TM", "TR", "TW", "UZ", "VN", "YE",
+];
+const EUROPE: &[&str] = &[
+    "AD", "AL", "AT", "AX", "BA", "BE", "BG", "BY", "CH", "CZ", "DE", "DK", "EE", "ES", "FI", "FO", "FR", "GB", "GG",
+    "GI", "GR", "HR", "HU", "IE", "IM", "IS", "IT", "JE", "LI", "LT", "LU", "LV", "MC", "MD", "ME", "MK", "MT", "NL",
+    "NO", "PL", "PT", "RO", "RS", "RU", "SE", "SI", "SJ", "SK", "SM", "UA", "VA", "XK",
+];
+const NORTH_AMERICA: &[&str] = &[
+    "AG", "AI", "AW", "BB", "BL", "BM", "BQ", "BS", "BZ", "CA", "CR", "CU", "CW", "DM", "DO", "GD", "GL", "GP", "GT",
+    "HN", "HT", "JM", "KN", "KY", "LC", "MF", "MQ", "MS", "MX", "NI", "PA", "PM", "PR", "SV", "SX", "TC", "TT", "US",
+    "VC", "VG", "VI",
+];
+const OCEANIA: &[&str] = &[
+    "AS", "AU", "CK", "FJ", "FM", "GU", "KI", "MH", "MP", "NC", "NF", "NR", "NU", "NZ", "PF", "PG", "PN", "PW", "SB",
+    "TK", "TO", "TV", "UM", "VU", "WF", "WS",
+];
+const SOUTH_AMERICA: &[&str] = &[
+    "AR", "BO", "BR", "CL", "CO", "EC", "FK", "GF", "GY", "PE", "PY", "SR", "UY", "VE",
+];
+
+pub fn continent(country: &str) -> Option<&'static str> {
+    let country = country.to_ascii_uppercase();
+
+    [
+        ("AF", AFRICA),
+        ("AN", ANTARCTICA),
+        ("AS", ASIA),
+        ("EU", EUROPE),
+        ("NA", NORTH_AMERICA),
+        ("OC", OCEANIA),
+        ("SA", SOUTH_AMERICA),
+    ]
+    .into_iter()
+    .find(|(_, countries)| countries.contains(&country.as_str()))
+    .map(|(continent, _)| continent)
+}
+
+/// Returns the first rule, in order, whose matchers all fit the visitor's location. A rule without any
+/// matchers always fits and acts as a fallback for the rules before it.
+pub fn find_match<'a>(rules: &'a [GeoRule], headers: &HeaderMap) -> Option<&'a GeoRule> {
+    let country = headers.string("cloudfront-viewer-country");
+    let region = headers.string("cloudfront-viewer-country-region");
+    let region_name = headers.string("cloudfront-viewer-country-region-name");
+    let continent = country.as_deref().and_then(continent);
+
+    let fits = |matcher: &Option<String>, value: Option<&str>| match matcher {
+        Some(matcher) => value.is_some_and(|value| value.eq_ignore_ascii_case(matcher)),
+        None => true,
+    };
+
+    rules.iter().find(|rule| {
+        fits(&rule.continent, continent)
+            && fits(&rule.country, country.as_deref())
+            && (fits(&rule.region, region.as_deref()) || fits(&rule.region, region_name.as_deref()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn rule(continent: Option<&str>, country: Option<&str>, region: Option<&str>, url: &str) -> GeoRule {
+        GeoRule {
+            id: 0,
+            continent: continent.map(String::from),
+            country: country.map(String::from),
+            region: region.map(String::from),
+            url: url.to_owned(),
+        }
+    }
+
+    fn location(country: &str, region: Option<&str>, region_name: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("cloudfront-viewer-country", HeaderValue::from_str(country).unwrap());
+
+        if let Some(region) = region {
+            headers.insert(
+                "cloudfront-viewer-country-region",
+                HeaderValue::from_str(region).unwrap(),
+            );
+        }
+
+        if let Some(region_name) = region_name {
+            headers.insert(
+                "cloudfront-viewer-country-region-name",
+                HeaderValue::from_str(region_name).unwrap(),
+            );
+        }
+
+        headers
+    }
+
+    fn matched<'a>(rules: &'a [GeoRule], headers: &HeaderMap) -> Option<&'a str> {
+        find_match(rules, headers).map(|rule| rule.url.as_str())
+    }
+
+    #[test]
+    fn the_first_fitting_rule_wins() {
+        let rules = [
+            rule(None, Some("US"), Some("CA"), "https://example.com/california"),
+            rule(Some("NA"), None, None, "https://example.com/north-america"),
+            rule(None, Some("us"), None, "https://example.com/us"),
+            rule(None, None, None, "https://example.com/elsewhere"),
+        ];
+
+        assert_eq!(
+            matched(&rules, &location("US", Some("CA"), None)),
+            Some("https://example.com/california")
+        );
+        assert_eq!(
+            matched(&rules, &location("US", Some("NY"), None)),
+            Some("https://example.com/north-america")
+        );
+        assert_eq!(
+            matched(&rules, &location("de", None, None)),
+            Some("https://example.com/elsewhere")
+        );
+        assert_eq!(
+            matched(&rules, &HeaderMap::new()),
+            Some("https://example.com/elsewhere")
+        );
+    }
+
+    #[test]
+    fn regions_match_by_code_or_name() {
+        let rules = [rule(None, Some("DE"), Some("Bavaria"), "https://example.com/bavaria")];
+
+        assert_eq!(
+            matched(&rules, &location("DE", Some("BY"), Some("Bavaria"))),
+            Some("https://example.com/bavaria")
+        );
+        assert_eq!(matched(&rules, &location("DE", Some("BE"), Some("Berlin"))), None);
+        assert_eq!(matched(&rules, &location("AT", Some("BY"), Some("Bavaria"))), None);
+    }
+
+    #[test]
+    fn countries_belong_to_their_continent() {
+        assert_eq!(continent("de"), Some("EU"));
+        assert_eq!(continent("BR"), Some("SA"));
+        assert_eq!(continent("ZZ"), None);
+    }
+}
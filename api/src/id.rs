@@ -1,3 +1,5 @@
+use std::fmt;
+
 use rand::Rng;
 
 const ID_LENGTH: usize = 8;
@@ -7,6 +9,33 @@ const ALPHABET: &[char] = &[
     'W', 'X', 'Y', 'Z',
 ];
 
+const ALIAS_MIN_LENGTH: usize = 3;
+const ALIAS_MAX_LENGTH: usize = 64;
+
+// Keys that would shadow routes mounted next to the redirect route. `c` and `q` are shorter than any alias, they are
+// listed so the routes stay covered if the minimum length ever changes.
+const RESERVED_ALIASES: &[&str] = &["api", "auth", "apple-app-site-association", "c", "q"];
+
+#[derive(Debug)]
+pub enum AliasError {
+    Length,
+    Charset,
+    Reserved,
+}
+
+impl fmt::Display for AliasError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AliasError::Length => write!(
+                f,
+                "alias must be between {ALIAS_MIN_LENGTH} and {ALIAS_MAX_LENGTH} characters long"
+            ),
+            AliasError::Charset => write!(f, "alias may only contain letters, digits, '-' and '_'"),
+            AliasError::Reserved => write!(f, "alias is reserved"),
+        }
+    }
+}
+
 pub fn generate_id() -> String {
     let mut random = rand::thread_rng();
 
@@ -14,3 +43,38 @@ pub fn generate_id() -> String {
         .map(|_| ALPHABET[random.gen_range(0..ALPHABET.len())])
         .collect()
 }
+
+pub fn validate_alias(alias: &str) -> Result<(), AliasError> {
+    if !(ALIAS_MIN_LENGTH..=ALIAS_MAX_LENGTH).contains(&alias.len()) {
+        return Err(AliasError::Length);
+    }
+
+    if !alias.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(AliasError::Charset);
+    }
+
+    if RESERVED_ALIASES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(alias))
+    {
+        return Err(AliasError::Reserved);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases_of_routes_are_reserved() {
+        for alias in ["api", "AUTH", "apple-app-site-association"] {
+            assert!(matches!(validate_alias(alias), Err(AliasError::Reserved)), "{alias}");
+        }
+
+        assert!(matches!(validate_alias("q"), Err(AliasError::Length)));
+        assert!(matches!(validate_alias(".well-known"), Err(AliasError::Charset)));
+        assert!(validate_alias("apple-app").is_ok());
+    }
+}
@@ -0,0 +1,95 @@
+use crate::entities::LanguageRule;
+
+/// Parses an `Accept-Language` header into lowercase language tags ordered by their q-value. Tags with
+/// `q=0` and the `*` wildcard are dropped, ties keep the order of the header.
+pub fn preferences(header: &str) -> Vec<String> {
+    let mut languages: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let tag = parts.next()?.trim().to_ascii_lowercase();
+
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map(|q| q.trim().parse::<f32>().unwrap_or(0.0))
+                .unwrap_or(1.0);
+
+            (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+
+    languages.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    languages.into_iter().map(|(tag, _)| tag).collect()
+}
+
+/// Finds the rule for the most preferred language. A tag that has no rule falls back to its less specific
+/// forms before the next preference is tried, so `de-AT` is served by a `de` rule.
+pub fn negotiate<'a>(rules: &'a [LanguageRule], header: &str) -> Option<&'a LanguageRule> {
+    preferences(header).iter().find_map(|tag| {
+        let mut tag = tag.as_str();
+
+        loop {
+            if let Some(rule) = rules.iter().find(|rule| rule.language == tag) {
+                return Some(rule);
+            }
+
+            tag = &tag[..tag.rfind('-')?];
+        }
+    })
+}
+
+pub fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag.len() <= 35
+        && tag
+            .split('-')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(languages: &[&str]) -> Vec<LanguageRule> {
+        languages
+            .iter()
+            .map(|language| LanguageRule {
+                language: language.to_string(),
+                url: format!("https://example.com/{language}"),
+            })
+            .collect()
+    }
+
+    fn negotiated<'a>(rules: &'a [LanguageRule], header: &str) -> Option<&'a str> {
+        negotiate(rules, header).map(|rule| rule.language.as_str())
+    }
+
+    #[test]
+    fn preferences_are_ordered_by_quality() {
+        assert_eq!(
+            preferences("fr;q=0.5, EN-us, de;q=0.8, *;q=0.1, it;q=0, es"),
+            ["en-us", "es", "de", "fr"]
+        );
+        assert_eq!(preferences("en;q=abc, de"), ["de"]);
+        assert!(preferences("").is_empty());
+    }
+
+    #[test]
+    fn the_most_preferred_language_with_a_rule_wins() {
+        let rules = rules(&["de", "fr-ca", "en"]);
+
+        assert_eq!(negotiated(&rules, "fr-CA, en;q=0.9"), Some("fr-ca"));
+        assert_eq!(negotiated(&rules, "ja, en;q=0.5, de;q=0.8"), Some("de"));
+        assert_eq!(negotiated(&rules, "en;q=0, de;q=0.1"), Some("de"));
+        assert_eq!(negotiated(&rules, "ja, *"), None);
+    }
+
+    #[test]
+    fn tags_fall_back_to_their_less_specific_forms() {
+        let rules = rules(&["de", "fr"]);
+
+        assert_eq!(negotiated(&rules, "de-AT-x-private"), Some("de"));
+        assert_eq!(negotiated(&rules, "fr-CA, de;q=0.9"), Some("fr"));
+        assert_eq!(negotiated(&rules, "pt-BR"), None);
+    }
+}
@@ -0,0 +1,302 @@
+use rusqlite::{Connection, OptionalExtension, Row};
+use time::OffsetDateTime;
+
+use crate::entities::{
+    DeepLink, Device, DeviceRule, GeoRule, LanguageRule, Link, MobileApp, Pixel, PixelKind, QueryConflict,
+    RedirectType, RotationDestination, RotationMode, ScheduleWindow, UrlVariant, Visibility,
+};
+
+pub const LINK_COLUMNS: &str = r"id, key, (SELECT hostname FROM domains WHERE domains.id = urls.domain_id) AS domain,
+    url, user_id, unixepoch(created_at) AS created_at, expires_at, max_clicks, clicks, fallback_url, revision_id,
+    redirect_type, forward_query, query_conflict, forward_path, active_from, active_until,
+    password_hash, visibility, require_signature, og_title, og_description, og_image, pixel_delay_ms, rotation";
+
+pub fn link_from_row(row: &Row) -> Result<Link, rusqlite::Error> {
+    let created_at: i64 = row.get("created_at")?;
+    let expires_at: Option<i64> = row.get("expires_at")?;
+    let redirect_type: String = row.get("redirect_type")?;
+    let query_conflict: String = row.get("query_conflict")?;
+    let active_from: Option<i64> = row.get("active_from")?;
+    let active_until: Option<i64> = row.get("active_until")?;
+    let visibility: String = row.get("visibility")?;
+    let rotation: Option<String> = row.get("rotation")?;
+
+    Ok(Link {
+        id: row.get("id")?,
+        key: row.get("key")?,
+        domain: row.get("domain")?,
+        url: row.get("url")?,
+        user_id: row.get("user_id")?,
+        created_at: timestamp(created_at),
+        expires_at: expires_at.map(timestamp),
+        max_clicks: row.get("max_clicks")?,
+        clicks: row.get("clicks")?,
+        fallback_url: row.get("fallback_url")?,
+        revision_id: row.get("revision_id")?,
+        redirect_type: RedirectType::parse(&redirect_type).unwrap_or_default(),
+        forward_query: row.get("forward_query")?,
+        query_conflict: QueryConflict::parse(&query_conflict).unwrap_or_default(),
+        forward_path: row.get("forward_path")?,
+        active_from: active_from.map(timestamp),
+        active_until: active_until.map(timestamp),
+        password_hash: row.get("password_hash")?,
+        visibility: Visibility::parse(&visibility).unwrap_or_default(),
+        require_signature: row.get("require_signature")?,
+        og_title: row.get("og_title")?,
+        og_description: row.get("og_description")?,
+        og_image: row.get("og_image")?,
+        pixel_delay_ms: row.get("pixel_delay_ms")?,
+        rotation: rotation.as_deref().and_then(RotationMode::parse),
+    })
+}
+
+/// Looks up a link by its key on a domain, ignoring links that have been deleted. `None` is the shared domain.
+pub fn find_link(
+    connection: &mut Connection,
+    domain_id: Option<i64>,
+    key: &str,
+) -> Result<Option<Link>, rusqlite::Error> {
+    let mut query = connection.prepare_cached(&format!(
+        "SELECT {LINK_COLUMNS} FROM urls WHERE domain_id IS ?1 AND key = ?2 AND deleted_at IS NULL"
+    ))?;
+
+    query.query_row((domain_id, key), link_from_row).optional()
+}
+
+/// The owner's display name, only if they chose to show it publicly.
+pub fn find_public_display_name(connection: &mut Connection, user_id: i64) -> Result<Option<String>, rusqlite::Error> {
+    let mut query = connection.prepare_cached("SELECT display_name FROM users WHERE id = ?1 AND public_profile = 1")?;
+
+    query
+        .query_row([user_id], |row| row.get("display_name"))
+        .optional()
+        .map(Option::flatten)
+}
+
+/// Whether any rule can send visits of the link to a destination other than its own.
+pub fn has_rules(connection: &mut Connection, link_id: i64) -> Result<bool, rusqlite::Error> {
+    let mut query = connection.prepare_cached(
+        r"SELECT EXISTS (SELECT 1 FROM device_rules WHERE url_id = ?1)
+            OR EXISTS (SELECT 1 FROM geo_rules WHERE url_id = ?1)
+            OR EXISTS (SELECT 1 FROM url_variants WHERE url_id = ?1)
+            OR EXISTS (SELECT 1 FROM url_schedules WHERE url_id = ?1)
+            OR EXISTS (SELECT 1 FROM language_rules WHERE url_id = ?1)
+            OR EXISTS (SELECT 1 FROM url_rotations WHERE url_id = ?1)",
+    )?;
+
+    query.query_row([link_id], |row| row.get(0))
+}
+
+pub fn find_device_rules(connection: &mut Connection, link_id: i64) -> Result<Vec<DeviceRule>, rusqlite::Error> {
+    let mut query =
+        connection.prepare_cached("SELECT device, url FROM device_rules WHERE url_id = ?1 ORDER BY device")?;
+
+    let rules = query
+        .query_map([link_id], |row| {
+            let device: String = row.get("device")?;
+
+            Ok((Device::parse(&device), row.get("url")?))
+        })?
+        .filter_map(|rule| match rule {
+            Ok((Some(device), url)) => Some(Ok(DeviceRule { device, url })),
+            Ok((None, _)) => None,
+            Err(err) => Some(Err(err)),
+        })
+        .collect::<Result<Vec<DeviceRule>, _>>()?;
+
+    Ok(rules)
+}
+
+pub fn find_device_url(
+    connection: &mut Connection,
+    link_id: i64,
+    device: Device,
+) -> Result<Option<String>, rusqlite::Error> {
+    let mut query = connection.prepare_cached("SELECT url FROM device_rules WHERE url_id = ?1 AND device = ?2")?;
+
+    query
+        .query_row((link_id, device.as_str()), |row| row.get("url"))
+        .optional()
+}
+
+pub fn find_geo_rules(connection: &mut Connection, link_id: i64) -> Result<Vec<GeoRule>, rusqlite::Error> {
+    let mut query = connection.prepare_cached(
+        "SELECT id, continent, country, region, url FROM geo_rules WHERE url_id = ?1 ORDER BY position",
+    )?;
+
+    let rules = query
+        .query_map([link_id], |row| {
+            Ok(GeoRule {
+                id: row.get("id")?,
+                continent: row.get("continent")?,
+                country: row.get("country")?,
+                region: row.get("region")?,
+                url: row.get("url")?,
+            })
+        })?
+        .collect::<Result<Vec<GeoRule>, _>>()?;
+
+    Ok(rules)
+}
+
+/// Pixels with an unknown kind are skipped.
+pub fn find_pixels(connection: &mut Connection, link_id: i64) -> Result<Vec<Pixel>, rusqlite::Error> {
+    let mut query = connection.prepare_cached("SELECT kind, url FROM url_pixels WHERE url_id = ?1 ORDER BY id")?;
+
+    let pixels = query
+        .query_map([link_id], |row| {
+            let kind: String = row.get("kind")?;
+
+            Ok((PixelKind::parse(&kind), row.get("url")?))
+        })?
+        .filter_map(|pixel| match pixel {
+            Ok((Some(kind), url)) => Some(Ok(Pixel { kind, url })),
+            Ok((None, _)) => None,
+            Err(err) => Some(Err(err)),
+        })
+        .collect::<Result<Vec<Pixel>, _>>()?;
+
+    Ok(pixels)
+}
+
+pub fn find_rotation(connection: &Connection, link_id: i64) -> Result<Vec<RotationDestination>, rusqlite::Error> {
+    let mut query =
+        connection.prepare_cached("SELECT id, url, hits FROM url_rotations WHERE url_id = ?1 ORDER BY position")?;
+
+    let destinations = query
+        .query_map([link_id], |row| {
+            Ok(RotationDestination {
+                id: row.get("id")?,
+                url: row.get("url")?,
+                hits: row.get("hits")?,
+            })
+        })?
+        .collect::<Result<Vec<RotationDestination>, _>>()?;
+
+    Ok(destinations)
+}
+
+pub fn find_deep_link(connection: &mut Connection, link_id: i64) -> Result<Option<DeepLink>, rusqlite::Error> {
+    let mut query = connection
+        .prepare_cached("SELECT app_url, ios_store_url, android_store_url FROM deep_links WHERE url_id = ?1")?;
+
+    query
+        .query_row([link_id], |row| {
+            Ok(DeepLink {
+                app_url: row.get("app_url")?,
+                ios_store_url: row.get("ios_store_url")?,
+                android_store_url: row.get("android_store_url")?,
+            })
+        })
+        .optional()
+}
+
+/// Keys of deep links on a domain that can still be visited, these are the paths the apps claim on it. `None` is
+/// the shared domain.
+pub fn find_deep_link_keys(
+    connection: &mut Connection,
+    domain_id: Option<i64>,
+) -> Result<Vec<String>, rusqlite::Error> {
+    let mut query = connection.prepare_cached(
+        r"SELECT urls.key FROM deep_links
+            JOIN urls ON urls.id = deep_links.url_id
+          WHERE urls.deleted_at IS NULL AND urls.domain_id IS ?1
+          ORDER BY urls.key",
+    )?;
+
+    let keys = query
+        .query_map([domain_id], |row| row.get("key"))?
+        .collect::<Result<Vec<String>, _>>()?;
+
+    Ok(keys)
+}
+
+/// Apps with an unknown platform are skipped.
+pub fn find_mobile_apps(connection: &mut Connection) -> Result<Vec<MobileApp>, rusqlite::Error> {
+    let mut query = connection
+        .prepare_cached("SELECT platform, app_id, store_url, sha256_cert_fingerprints FROM mobile_apps ORDER BY id")?;
+
+    let apps = query
+        .query_map([], |row| {
+            let platform: String = row.get("platform")?;
+            let app_id: String = row.get("app_id")?;
+            let store_url: Option<String> = row.get("store_url")?;
+            let fingerprints: String = row.get("sha256_cert_fingerprints")?;
+
+            Ok(Device::parse(&platform).map(|platform| MobileApp {
+                platform,
+                app_id,
+                store_url,
+                sha256_cert_fingerprints: fingerprints
+                    .split(',')
+                    .filter(|fingerprint| !fingerprint.is_empty())
+                    .map(String::from)
+                    .collect(),
+            }))
+        })?
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<MobileApp>, _>>()?;
+
+    Ok(apps)
+}
+
+pub fn find_language_rules(connection: &mut Connection, link_id: i64) -> Result<Vec<LanguageRule>, rusqlite::Error> {
+    let mut query =
+        connection.prepare_cached("SELECT language, url FROM language_rules WHERE url_id = ?1 ORDER BY language")?;
+
+    let rules = query
+        .query_map([link_id], |row| {
+            Ok(LanguageRule {
+                language: row.get("language")?,
+                url: row.get("url")?,
+            })
+        })?
+        .collect::<Result<Vec<LanguageRule>, _>>()?;
+
+    Ok(rules)
+}
+
+pub fn find_variants(connection: &mut Connection, link_id: i64) -> Result<Vec<UrlVariant>, rusqlite::Error> {
+    let mut query =
+        connection.prepare_cached("SELECT id, name, url, weight FROM url_variants WHERE url_id = ?1 ORDER BY id")?;
+
+    let variants = query
+        .query_map([link_id], |row| {
+            Ok(UrlVariant {
+                id: row.get("id")?,
+                name: row.get("name")?,
+                url: row.get("url")?,
+                weight: row.get("weight")?,
+            })
+        })?
+        .collect::<Result<Vec<UrlVariant>, _>>()?;
+
+    Ok(variants)
+}
+
+pub fn find_schedule(connection: &mut Connection, link_id: i64) -> Result<Vec<ScheduleWindow>, rusqlite::Error> {
+    let mut query = connection.prepare_cached(
+        "SELECT id, starts_at, ends_at, local_time, url FROM url_schedules WHERE url_id = ?1 ORDER BY id",
+    )?;
+
+    let windows = query
+        .query_map([link_id], |row| {
+            let starts_at: Option<i64> = row.get("starts_at")?;
+            let ends_at: Option<i64> = row.get("ends_at")?;
+
+            Ok(ScheduleWindow {
+                id: row.get("id")?,
+                starts_at: starts_at.map(timestamp),
+                ends_at: ends_at.map(timestamp),
+                local_time: row.get("local_time")?,
+                url: row.get("url")?,
+            })
+        })?
+        .collect::<Result<Vec<ScheduleWindow>, _>>()?;
+
+    Ok(windows)
+}
+
+pub fn timestamp(unix_timestamp: i64) -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp(unix_timestamp).unwrap_or(OffsetDateTime::UNIX_EPOCH)
+}
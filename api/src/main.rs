@@ -1,19 +1,39 @@
 #![feature(let_chains)]
+mod blocklist;
+mod branding;
+mod device;
+mod dns;
+mod domains;
 mod entities;
+mod geo;
 mod headers;
 mod id;
+mod language;
+mod links;
 mod metrics;
 mod middleware;
+mod pages;
+mod passthrough;
+mod pixels;
 mod postgres;
+mod qr;
+mod rate_limit;
 mod routes;
+mod schedule;
+mod signing;
 mod sqlite;
 mod structs;
+mod unfurl;
+mod validation;
+mod variants;
 
-use axum::middleware::from_fn_with_state;
+use axum::middleware::{from_fn, from_fn_with_state};
+use blocklist::Blocklist;
 use middleware::auth::AuthMiddlewareState;
-use routes::{api, auth, shorten};
+use routes::{admin, api, auth, shorten};
+use signing::LinkSigner;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use tokio::sync::Mutex;
 
 use axum::Router;
@@ -32,11 +52,21 @@ async fn main() {
     }));
 
     let auth_middleware = from_fn_with_state(middleware_state, middleware::auth::authorization_middleware);
+    let admin_middleware = from_fn(middleware::auth::admin_middleware);
+
+    let blocklist = Arc::new(RwLock::new(Blocklist::from_env()));
+    blocklist::watch(blocklist.clone());
+
+    let link_signer = Arc::new(LinkSigner::from_env());
+
+    let api_router = api::router(pg_conn, blocklist.clone(), link_signer.clone())
+        .nest("/admin", admin::router(blocklist.clone()).layer(admin_middleware))
+        .layer(auth_middleware);
 
     let app = Router::new()
-        .merge(shorten::router(pg_pool))
+        .merge(shorten::router(pg_pool, blocklist, link_signer))
         .nest("/auth", auth::router())
-        .nest("/api", api::router(pg_conn).layer(auth_middleware));
+        .nest("/api", api_router);
 
     println!("API started!");
 
@@ -6,6 +6,8 @@ use tokio_postgres::Error;
 use tokio_postgres::{binary_copy::BinaryCopyInWriter, types::ToSql};
 use uuid::Uuid;
 
+use crate::entities::ClickSource;
+
 const COPY_STMT: &str = r"COPY metrics (
   id, 
   key, 
@@ -23,12 +25,22 @@ const COPY_STMT: &str = r"COPY metrics (
   user_agent, 
   visitor_id,
   created_at,
-  location
+  location,
+  revision_id,
+  geo_rule_id,
+  variant_id,
+  language,
+  visitor_user_id,
+  rotation_id,
+  source,
+  domain
 ) FROM STDIN BINARY";
 
 pub struct Metric {
     pub visitor_id: String,
     pub shorthand_id: String,
+    /// Hostname of the custom domain the link is on, keys are only unique within a domain.
+    pub domain: Option<String>,
     pub user_id: i64,
     pub created_at: OffsetDateTime,
     pub url: String,
@@ -44,6 +56,13 @@ pub struct Metric {
     pub user_agent: Option<String>,
     pub longitude: Option<f64>,
     pub latitude: Option<f64>,
+    pub revision_id: Option<i64>,
+    pub geo_rule_id: Option<i64>,
+    pub variant_id: Option<i64>,
+    pub language: Option<String>,
+    pub visitor_user_id: Option<i64>,
+    pub rotation_id: Option<i64>,
+    pub source: ClickSource,
 }
 
 pub async fn persist_metrics(mut client: deadpool_postgres::Object, metrics: Vec<Metric>) -> Result<(), Error> {
@@ -67,6 +86,14 @@ pub async fn persist_metrics(mut client: deadpool_postgres::Object, metrics: Vec
         Type::TEXT,
         Type::TIMESTAMPTZ,
         geography_type,
+        Type::INT8,
+        Type::INT8,
+        Type::INT8,
+        Type::TEXT,
+        Type::INT8,
+        Type::INT8,
+        Type::TEXT,
+        Type::TEXT,
     ];
 
     let transaction = client.transaction().await?;
@@ -107,6 +134,14 @@ pub async fn persist_metrics(mut client: deadpool_postgres::Object, metrics: Vec
                 &metric.visitor_id,
                 &metric.created_at,
                 &location,
+                &metric.revision_id,
+                &metric.geo_rule_id,
+                &metric.variant_id,
+                &metric.language,
+                &metric.visitor_user_id,
+                &metric.rotation_id,
+                &metric.source.as_str(),
+                &metric.domain,
             ])
             .await?;
     }
@@ -116,3 +151,29 @@ pub async fn persist_metrics(mut client: deadpool_postgres::Object, metrics: Vec
 
     Ok(())
 }
+
+pub struct Conversion {
+    pub user_id: i64,
+    pub goal: String,
+    pub visitor_id: String,
+    pub value: Option<f64>,
+    pub created_at: OffsetDateTime,
+}
+
+/// Conversions are rare compared to clicks, so they are written one by one instead of being buffered.
+pub async fn persist_conversion(client: deadpool_postgres::Object, conversion: Conversion) -> Result<(), Error> {
+    client
+        .execute(
+            "INSERT INTO conversions (id, user_id, goal, visitor_id, value, created_at) VALUES ($1, $2, $3, $4, $5, $6)",
+            &[
+                &Uuid::now_v7().to_string(),
+                &conversion.user_id,
+                &conversion.goal,
+                &conversion.visitor_id,
+                &conversion.value,
+                &conversion.created_at,
+            ],
+        )
+        .await
+        .map(|_| ())
+}
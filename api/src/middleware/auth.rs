@@ -49,17 +49,26 @@ pub async fn authorization_middleware(
     Ok(next.run(req).await)
 }
 
+pub async fn admin_middleware(req: Request, next: Next) -> Result<Response, StatusCode> {
+    match req.extensions().get::<UserSession>() {
+        Some(session) if session.user.is_admin => Ok(next.run(req).await),
+        Some(_) => Err(StatusCode::FORBIDDEN),
+        None => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
 pub fn find_user_by_session_id(connection: &mut Connection, session_id: &str) -> Result<User, rusqlite::Error> {
     connection.query_row(
-        r"SELECT id, email FROM users WHERE id = (
+        r"SELECT id, email, is_admin FROM users WHERE id = (
               SELECT user_id FROM sessions WHERE session_id = ?1 AND unixepoch() <= expires_at
             )",
         [session_id],
         |row| {
             let id: i64 = row.get("id")?;
             let email: String = row.get("email")?;
+            let is_admin: bool = row.get("is_admin")?;
 
-            Ok(User { email, id })
+            Ok(User { email, id, is_admin })
         },
     )
 }
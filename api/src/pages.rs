@@ -0,0 +1,259 @@
+use time::OffsetDateTime;
+
+use crate::{
+    entities::{Pixel, PixelKind},
+    unfurl::Metadata,
+};
+
+// Long enough for the OS to switch to the app before the page gives up on it
+const APP_HANDOFF_TIMEOUT_MS: u32 = 1500;
+
+/// Pixel scripts come from third parties, the sandbox gives the interstitial an opaque origin so they can't read
+/// the short domain's cookies or storage, while still letting them run and the page move on to the destination.
+pub const INTERSTITIAL_POLICY: &str = "sandbox allow-scripts allow-top-navigation";
+
+pub fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn layout(title: &str, head: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+  <head>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <meta name="robots" content="noindex">
+    <title>{title}</title>
+    {head}
+  </head>
+  <body style="font-family: sans-serif; max-width: 40rem; margin: 4rem auto; padding: 0 1rem;">
+    {body}
+  </body>
+</html>"#,
+        title = escape(title),
+    )
+}
+
+pub fn blocked(url: &str) -> String {
+    layout(
+        "Link blocked",
+        "",
+        &format!(
+            r#"<h1>Warning: this link has been blocked</h1>
+    <p>The destination of this short link was reported as malicious, so we did not redirect you.</p>
+    <p><code>{}</code></p>"#,
+            escape(url)
+        ),
+    )
+}
+
+pub fn meta_refresh(url: &str) -> String {
+    let url = escape(url);
+
+    layout(
+        "Redirecting",
+        &format!(r#"<meta http-equiv="refresh" content="0; url={url}">"#),
+        &format!(r#"<p>Redirecting to <a href="{url}">{url}</a></p>"#),
+    )
+}
+
+pub fn password_form(error: Option<&str>) -> String {
+    let error = error
+        .map(|error| format!(r#"<p style="color: #b00020;">{}</p>"#, escape(error)))
+        .unwrap_or_default();
+
+    layout(
+        "Password required",
+        "",
+        &format!(
+            r#"<h1>This link is password protected</h1>
+    {error}
+    <form method="post">
+      <input type="password" name="password" autofocus required>
+      <button type="submit">Open link</button>
+    </form>"#
+        ),
+    )
+}
+
+pub fn login_form(return_to: &str, error: Option<&str>) -> String {
+    let error = error
+        .map(|error| format!(r#"<p style="color: #b00020;">{}</p>"#, escape(error)))
+        .unwrap_or_default();
+
+    layout(
+        "Log in",
+        "",
+        &format!(
+            r#"<h1>Log in to open this link</h1>
+    {error}
+    <form method="post" action="/auth/login/form">
+      <input type="hidden" name="return_to" value="{return_to}">
+      <p><input type="email" name="email" placeholder="Email" autofocus required></p>
+      <p><input type="password" name="password" placeholder="Password" required></p>
+      <button type="submit">Log in</button>
+    </form>"#,
+            return_to = escape(return_to),
+        ),
+    )
+}
+
+pub fn preview(key: &str, url: &str, created_at: OffsetDateTime, owner: Option<&str>, clicks: i64) -> String {
+    let url = escape(url);
+    let owner = owner
+        .map(|owner| format!("<dt>Created by</dt><dd>{}</dd>", escape(owner)))
+        .unwrap_or_default();
+    let created = format!(
+        "{}-{:02}-{:02}",
+        created_at.year(),
+        u8::from(created_at.month()),
+        created_at.day()
+    );
+
+    layout(
+        "Link preview",
+        "",
+        &format!(
+            r#"<h1>Where does /{key} go?</h1>
+    <p><a href="{url}" rel="noopener noreferrer nofollow"><code>{url}</code></a></p>
+    <dl>
+      <dt>Created</dt><dd>{created}</dd>
+      {owner}
+      <dt>Clicks</dt><dd>{clicks}</dd>
+    </dl>"#,
+            key = escape(key),
+        ),
+    )
+}
+
+/// Served to link preview bots instead of the redirect, humans that end up here are sent on right away.
+pub fn unfurl(url: &str, metadata: &Metadata) -> String {
+    let title = metadata.title.as_deref().unwrap_or(url);
+    let url = escape(url);
+
+    let mut head = format!(
+        r#"<meta property="og:url" content="{url}">
+    <meta property="og:title" content="{}">
+    <meta http-equiv="refresh" content="0; url={url}">"#,
+        escape(title)
+    );
+
+    if let Some(description) = &metadata.description {
+        head.push_str(&format!(
+            r#"
+    <meta property="og:description" content="{}">"#,
+            escape(description)
+        ));
+    }
+
+    if let Some(image) = &metadata.image {
+        head.push_str(&format!(
+            r#"
+    <meta property="og:image" content="{}">
+    <meta name="twitter:card" content="summary_large_image">"#,
+            escape(image)
+        ));
+    }
+
+    layout(
+        title,
+        &head,
+        &format!(r#"<p><a href="{url}">{}</a></p>"#, escape(title)),
+    )
+}
+
+/// Loads the pixels and sends the visitor on after `delay_ms`, the meta refresh covers visitors without JavaScript.
+pub fn pixel_interstitial(url: &str, pixels: &[Pixel], delay_ms: u32) -> String {
+    let url = escape(url);
+    let mut head = format!(
+        r#"<meta http-equiv="refresh" content="{}; url={url}">"#,
+        delay_ms.div_ceil(1000)
+    );
+    let mut images = String::new();
+
+    for pixel in pixels {
+        match pixel.kind {
+            PixelKind::Script => head.push_str(&format!(
+                r#"
+    <script src="{}" async></script>"#,
+                escape(&pixel.url)
+            )),
+            PixelKind::Image => images.push_str(&format!(
+                r#"
+    <img src="{}" width="1" height="1" alt="" style="position: absolute; visibility: hidden;">"#,
+                escape(&pixel.url)
+            )),
+        }
+    }
+
+    head.push_str(&format!(
+        r#"
+    <script>
+      setTimeout(function () {{
+        window.location.replace(document.getElementById("destination").href);
+      }}, {delay_ms});
+    </script>"#
+    ));
+
+    layout(
+        "Redirecting",
+        &head,
+        &format!(r#"<p>Redirecting to <a id="destination" href="{url}">{url}</a></p>{images}"#),
+    )
+}
+
+/// Tries to open the app and falls back to the store, or the destination without one, when the page is still
+/// visible after a moment, which is how it looks when the app isn't installed.
+pub fn app_handoff(app_url: &str, store_url: Option<&str>, destination: &str) -> String {
+    let app_url = escape(app_url);
+    let destination = escape(destination);
+    let store_link = store_url
+        .map(|store_url| {
+            format!(
+                r#"<p><a id="fallback" href="{}">Get the app</a></p>"#,
+                escape(store_url)
+            )
+        })
+        .unwrap_or_default();
+
+    let head = format!(
+        r#"<script>
+      document.addEventListener("DOMContentLoaded", function () {{
+        var fallback = document.getElementById("fallback") || document.getElementById("destination");
+        var timer = setTimeout(function () {{
+          if (!document.hidden) window.location.replace(fallback.href);
+        }}, {APP_HANDOFF_TIMEOUT_MS});
+
+        document.addEventListener("visibilitychange", function () {{
+          if (document.hidden) clearTimeout(timer);
+        }});
+
+        window.location.href = document.getElementById("app").href;
+      }});
+    </script>"#
+    );
+
+    layout(
+        "Opening app",
+        &head,
+        &format!(
+            r#"<p><a id="app" href="{app_url}">Open in the app</a></p>
+    {store_link}
+    <p><a id="destination" href="{destination}">Continue in the browser</a></p>"#
+        ),
+    )
+}
@@ -0,0 +1,128 @@
+use url::Url;
+
+use crate::entities::{Link, QueryConflict};
+
+/// Builds the final destination by forwarding the incoming query string and the path after the key to `target`,
+/// if the link allows it. Returns `None` if a path was given for a link that doesn't forward paths.
+pub fn destination(link: &Link, target: &str, query: Option<&str>, rest: Option<&str>) -> Option<String> {
+    let rest = rest.filter(|rest| !rest.is_empty());
+    let query = query.filter(|query| link.forward_query && !query.is_empty());
+
+    if rest.is_some() && !link.forward_path {
+        return None;
+    }
+
+    if rest.is_none() && query.is_none() {
+        return Some(target.to_owned());
+    }
+
+    let mut url = Url::parse(target).ok()?;
+
+    if let Some(rest) = rest {
+        let path = format!("{}/{}", url.path().trim_end_matches('/'), rest.trim_start_matches('/'));
+        url.set_path(&path);
+    }
+
+    if let Some(query) = query {
+        let incoming: Vec<(String, String)> = url::form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+        let existing: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+
+        let mut merged: Vec<(String, String)> = match link.query_conflict {
+            QueryConflict::IncomingWins => existing
+                .into_iter()
+                .filter(|(key, _)| !contains_key(&incoming, key))
+                .collect(),
+            QueryConflict::DestinationWins | QueryConflict::KeepBoth => existing,
+        };
+
+        match link.query_conflict {
+            QueryConflict::DestinationWins => {
+                let forwarded: Vec<(String, String)> = incoming
+                    .into_iter()
+                    .filter(|(key, _)| !contains_key(&merged, key))
+                    .collect();
+
+                merged.extend(forwarded);
+            }
+            QueryConflict::IncomingWins | QueryConflict::KeepBoth => merged.extend(incoming),
+        }
+
+        url.query_pairs_mut().clear().extend_pairs(merged);
+    }
+
+    Some(url.into())
+}
+
+fn contains_key(pairs: &[(String, String)], key: &str) -> bool {
+    pairs.iter().any(|(existing, _)| existing == key)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{links, routes::api::api, sqlite};
+
+    fn link(query_conflict: &str, forward_path: bool) -> Link {
+        let mut connection = sqlite::create_test_connection();
+        let payload = serde_json::from_value(json!({
+            "url": "https://example.com/landing?utm_source=link&id=1",
+            "forward_query": true,
+            "query_conflict": query_conflict,
+            "forward_path": forward_path,
+        }))
+        .unwrap();
+        api::create_short_url(&mut connection, 1, "forwarded", None, &payload).unwrap();
+
+        links::find_link(&mut connection, None, "forwarded").unwrap().unwrap()
+    }
+
+    fn forward(link: &Link, query: Option<&str>, rest: Option<&str>) -> Option<String> {
+        destination(link, &link.url, query, rest)
+    }
+
+    #[test]
+    fn incoming_parameters_replace_the_destinations() {
+        let link = link("incoming_wins", false);
+
+        assert_eq!(
+            forward(&link, Some("utm_source=mail&ref=a"), None).unwrap(),
+            "https://example.com/landing?id=1&utm_source=mail&ref=a"
+        );
+    }
+
+    #[test]
+    fn destination_parameters_are_kept() {
+        let link = link("destination_wins", false);
+
+        assert_eq!(
+            forward(&link, Some("utm_source=mail&ref=a"), None).unwrap(),
+            "https://example.com/landing?utm_source=link&id=1&ref=a"
+        );
+    }
+
+    #[test]
+    fn both_values_are_kept_on_request() {
+        let link = link("keep_both", false);
+
+        assert_eq!(
+            forward(&link, Some("utm_source=mail"), None).unwrap(),
+            "https://example.com/landing?utm_source=link&id=1&utm_source=mail"
+        );
+    }
+
+    #[test]
+    fn paths_are_only_forwarded_when_allowed() {
+        let open = link("incoming_wins", true);
+
+        assert_eq!(
+            forward(&open, None, Some("docs/intro")).unwrap(),
+            "https://example.com/landing/docs/intro?utm_source=link&id=1"
+        );
+        assert_eq!(forward(&open, Some(""), Some("")).unwrap(), open.url);
+
+        let closed = link("incoming_wins", false);
+        assert_eq!(forward(&closed, None, Some("docs/intro")), None);
+    }
+}
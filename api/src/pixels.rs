@@ -0,0 +1,45 @@
+use rusqlite::Connection;
+use url::Url;
+
+/// Hosts whose pixels links may load, managed by admins. Subdomains of an allowed host are allowed as well, so
+/// local pixel endpoints can be used for testing by allowing `localhost`.
+pub fn find_hosts(connection: &mut Connection) -> Result<Vec<String>, rusqlite::Error> {
+    let mut query = connection.prepare_cached("SELECT host FROM pixel_hosts ORDER BY host")?;
+
+    let hosts = query
+        .query_map([], |row| row.get("host"))?
+        .collect::<Result<Vec<String>, _>>()?;
+
+    Ok(hosts)
+}
+
+pub fn add_host(connection: &mut Connection, host: &str) -> Result<bool, rusqlite::Error> {
+    connection
+        .prepare_cached("INSERT INTO pixel_hosts (host) VALUES (?1) ON CONFLICT (host) DO NOTHING")?
+        .execute([host])
+        .map(|inserted| inserted > 0)
+}
+
+pub fn remove_host(connection: &mut Connection, host: &str) -> Result<bool, rusqlite::Error> {
+    connection
+        .prepare_cached("DELETE FROM pixel_hosts WHERE host = ?1")?
+        .execute([host])
+        .map(|deleted| deleted > 0)
+}
+
+pub fn is_allowed(hosts: &[String], url: &str) -> bool {
+    let Ok(url) = Url::parse(url) else {
+        return false;
+    };
+
+    let Some(host) = url.host_str().filter(|_| matches!(url.scheme(), "http" | "https")) else {
+        return false;
+    };
+
+    hosts.iter().any(|allowed| {
+        host.eq_ignore_ascii_case(allowed)
+            || host.len() > allowed.len()
+                && host[host.len() - allowed.len()..].eq_ignore_ascii_case(allowed)
+                && host.as_bytes()[host.len() - allowed.len() - 1] == b'.'
+    })
+}
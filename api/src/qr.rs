@@ -0,0 +1,309 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use qrcode::{types::QrError, Color, EcLevel, QrCode};
+
+use crate::{structs::QrErrorCorrection, unfurl};
+
+const MAX_LOGO_SIZE: usize = 256 * 1024;
+const MAX_LOGO_DIMENSION: u32 = 1024;
+const MAX_LOGO_MEMORY: usize = 16 * 1024 * 1024;
+// Share of the code's width the logo may cover, the modules beneath it are restored by error correction
+const LOGO_SCALE: f64 = 0.2;
+// Blank border around the logo so it doesn't blend into the modules next to it
+const LOGO_PADDING: f64 = 0.1;
+
+pub type Rgb = [u8; 3];
+
+pub struct Style {
+    /// Width and height of the image in pixels.
+    pub size: u32,
+    /// Quiet zone around the code in modules.
+    pub margin: u32,
+    pub foreground: Rgb,
+    pub background: Rgb,
+}
+
+pub struct Logo {
+    png: Vec<u8>,
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+/// Where the logo goes in a code `code_size` units wide that starts at `offset`, in the units of the image.
+struct LogoBox {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    padding: f64,
+}
+
+impl LogoBox {
+    fn new(logo: &Logo, offset: f64, code_size: f64) -> LogoBox {
+        let side = code_size * LOGO_SCALE;
+        let scale = (side / logo.width as f64).min(side / logo.height as f64);
+        let (width, height) = (logo.width as f64 * scale, logo.height as f64 * scale);
+
+        LogoBox {
+            x: offset + (code_size - width) / 2.0,
+            y: offset + (code_size - height) / 2.0,
+            width,
+            height,
+            padding: side * LOGO_PADDING,
+        }
+    }
+}
+
+pub fn encode(data: &str, error_correction: QrErrorCorrection) -> Result<QrCode, QrError> {
+    let level = match error_correction {
+        QrErrorCorrection::Low => EcLevel::L,
+        QrErrorCorrection::Medium => EcLevel::M,
+        QrErrorCorrection::Quartile => EcLevel::Q,
+        QrErrorCorrection::High => EcLevel::H,
+    };
+
+    QrCode::with_error_correction_level(data, level)
+}
+
+/// Width of the code including the quiet zone, in modules.
+pub fn total_modules(code: &QrCode, margin: u32) -> u32 {
+    code.width() as u32 + 2 * margin
+}
+
+/// Accepts `#rrggbb` and `rrggbb`.
+pub fn parse_color(value: &str) -> Option<Rgb> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let channel = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).ok();
+
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// Downloads a PNG logo, anything else or anything too large is refused. `client` must come from
+/// [`unfurl::client`], which keeps the request and its redirects away from internal addresses.
+pub async fn fetch_logo(client: &reqwest::Client, url: &str) -> Option<Logo> {
+    if !unfurl::is_public_url(url) {
+        return None;
+    }
+
+    let mut response = client.get(url).send().await.ok()?;
+
+    let is_png = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("image/png"));
+
+    if !response.status().is_success()
+        || !is_png
+        || response
+            .content_length()
+            .is_some_and(|length| length > MAX_LOGO_SIZE as u64)
+    {
+        return None;
+    }
+
+    let mut png = Vec::new();
+
+    while let Some(chunk) = response.chunk().await.ok()? {
+        if png.len() + chunk.len() > MAX_LOGO_SIZE {
+            return None;
+        }
+
+        png.extend_from_slice(&chunk);
+    }
+
+    decode_logo(png)
+}
+
+fn decode_logo(png: Vec<u8>) -> Option<Logo> {
+    let mut decoder = png::Decoder::new_with_limits(png.as_slice(), png::Limits { bytes: MAX_LOGO_MEMORY });
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info().ok()?;
+
+    if reader.info().width > MAX_LOGO_DIMENSION || reader.info().height > MAX_LOGO_DIMENSION {
+        return None;
+    }
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).ok()?;
+    let pixels = &buffer[..info.buffer_size()];
+
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => pixels.to_vec(),
+        png::ColorType::Rgb => pixels.chunks(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => pixels.chunks(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => pixels.iter().flat_map(|&p| [p, p, p, 255]).collect(),
+        png::ColorType::Indexed => return None,
+    };
+
+    Some(Logo {
+        width: info.width,
+        height: info.height,
+        rgba,
+        png,
+    })
+}
+
+pub fn render_png(code: &QrCode, style: &Style, logo: Option<&Logo>) -> Result<Vec<u8>, png::EncodingError> {
+    let colors = code.to_colors();
+    let modules = code.width() as u32;
+    let total = total_modules(code, style.margin);
+    let size = style.size;
+
+    let mut pixels = Vec::with_capacity((size * size * 3) as usize);
+
+    // Nearest neighbour, so the image has exactly the requested size even when it isn't a multiple of the modules
+    for y in 0..size {
+        let row = (y * total / size)
+            .checked_sub(style.margin)
+            .filter(|&row| row < modules);
+
+        for x in 0..size {
+            let column = (x * total / size)
+                .checked_sub(style.margin)
+                .filter(|&column| column < modules);
+
+            let is_dark = match (row, column) {
+                (Some(row), Some(column)) => colors[(row * modules + column) as usize] == Color::Dark,
+                _ => false,
+            };
+
+            pixels.extend_from_slice(if is_dark { &style.foreground } else { &style.background });
+        }
+    }
+
+    if let Some(logo) = logo {
+        let module_size = size as f64 / total as f64;
+        let area = LogoBox::new(logo, style.margin as f64 * module_size, modules as f64 * module_size);
+        draw_logo(&mut pixels, size, style.background, logo, &area);
+    }
+
+    let mut image = Vec::new();
+    let mut encoder = png::Encoder::new(&mut image, size, size);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    writer.finish()?;
+
+    Ok(image)
+}
+
+fn draw_logo(pixels: &mut [u8], size: u32, background: Rgb, logo: &Logo, area: &LogoBox) {
+    let left = (area.x - area.padding).max(0.0) as u32;
+    let top = (area.y - area.padding).max(0.0) as u32;
+    let right = ((area.x + area.width + area.padding).ceil() as u32).min(size);
+    let bottom = ((area.y + area.height + area.padding).ceil() as u32).min(size);
+
+    for y in top..bottom {
+        for x in left..right {
+            let index = ((y * size + x) * 3) as usize;
+            let mut color = background;
+
+            let (logo_x, logo_y) = (x as f64 + 0.5 - area.x, y as f64 + 0.5 - area.y);
+
+            if (0.0..area.width).contains(&logo_x) && (0.0..area.height).contains(&logo_y) {
+                let source_x = ((logo_x / area.width * logo.width as f64) as u32).min(logo.width - 1);
+                let source_y = ((logo_y / area.height * logo.height as f64) as u32).min(logo.height - 1);
+                let source = ((source_y * logo.width + source_x) * 4) as usize;
+                let alpha = logo.rgba[source + 3] as u32;
+
+                for channel in 0..3 {
+                    let blended =
+                        (logo.rgba[source + channel] as u32 * alpha + background[channel] as u32 * (255 - alpha)) / 255;
+                    color[channel] = blended as u8;
+                }
+            }
+
+            pixels[index..index + 3].copy_from_slice(&color);
+        }
+    }
+}
+
+pub fn render_svg(code: &QrCode, style: &Style, logo: Option<&Logo>) -> String {
+    let colors = code.to_colors();
+    let modules = code.width();
+    let total = total_modules(code, style.margin);
+    let margin = style.margin as usize;
+
+    let mut path = String::new();
+
+    for (index, color) in colors.iter().enumerate() {
+        if *color == Color::Dark {
+            let (x, y) = (index % modules + margin, index / modules + margin);
+            path.push_str(&format!("M{x},{y}h1v1h-1z"));
+        }
+    }
+
+    let mut svg = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<svg xmlns="http://www.w3.org/2000/svg" width="{size}" height="{size}" viewBox="0 0 {total} {total}" shape-rendering="crispEdges">
+  <rect width="{total}" height="{total}" fill="{background}"/>
+  <path d="{path}" fill="{foreground}"/>
+"#,
+        size = style.size,
+        background = hex(style.background),
+        foreground = hex(style.foreground),
+    );
+
+    if let Some(logo) = logo {
+        let area = LogoBox::new(logo, style.margin as f64, modules as f64);
+
+        svg.push_str(&format!(
+            r#"  <rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>
+  <image x="{}" y="{}" width="{}" height="{}" href="data:image/png;base64,{}"/>
+"#,
+            area.x - area.padding,
+            area.y - area.padding,
+            area.width + 2.0 * area.padding,
+            area.height + 2.0 * area.padding,
+            hex(style.background),
+            area.x,
+            area.y,
+            area.width,
+            area.height,
+            STANDARD.encode(&logo.png),
+        ));
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn hex([red, green, blue]: Rgb) -> String {
+    format!("#{red:02x}{green:02x}{blue:02x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn logos_are_not_fetched_from_internal_hosts() {
+        let client = unfurl::client();
+
+        for url in [
+            "http://127.0.0.1:9/logo.png",
+            "http://[::ffff:127.0.0.1]:9/logo.png",
+            "http://localhost:9/logo.png",
+            "file:///etc/logo.png",
+        ] {
+            assert!(fetch_logo(&client, url).await.is_none(), "{url}");
+        }
+    }
+
+    #[test]
+    fn colors_are_parsed_with_or_without_hash() {
+        assert_eq!(parse_color("#ff8000"), Some([255, 128, 0]));
+        assert_eq!(parse_color("00ff00"), Some([0, 255, 0]));
+        assert_eq!(parse_color("#fff"), None);
+        assert_eq!(parse_color("zzzzzz"), None);
+    }
+}
@@ -0,0 +1,50 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+// Stale entries are only swept once the map grows beyond this size
+const SWEEP_THRESHOLD: usize = 10_000;
+
+/// Counts failed attempts per identifier within a fixed window.
+pub struct RateLimiter {
+    max_failures: u32,
+    window: Duration,
+    failures: HashMap<String, (u32, Instant)>,
+}
+
+impl RateLimiter {
+    pub fn new(max_failures: u32, window: Duration) -> Self {
+        RateLimiter {
+            max_failures,
+            window,
+            failures: HashMap::new(),
+        }
+    }
+
+    pub fn is_limited(&self, id: &str) -> bool {
+        self.failures
+            .get(id)
+            .is_some_and(|(count, started)| *count >= self.max_failures && started.elapsed() < self.window)
+    }
+
+    pub fn record_failure(&mut self, id: &str) {
+        if self.failures.len() >= SWEEP_THRESHOLD {
+            let window = self.window;
+            self.failures.retain(|_, (_, started)| started.elapsed() < window);
+        }
+
+        let window = self.window;
+        let entry = self.failures.entry(id.to_owned()).or_insert((0, Instant::now()));
+
+        if entry.1.elapsed() >= window {
+            *entry = (0, Instant::now());
+        }
+
+        entry.0 += 1;
+    }
+
+    pub fn reset(&mut self, id: &str) {
+        self.failures.remove(id);
+    }
+}
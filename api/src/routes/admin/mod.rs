@@ -0,0 +1,243 @@
+pub mod queries;
+
+use std::sync::{Arc, PoisonError};
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::get, Json, Router};
+use rusqlite::Connection;
+use tokio::sync::Mutex;
+use url::Url;
+
+use crate::{
+    blocklist::SharedBlocklist,
+    links, pixels, sqlite,
+    structs::{
+        BlocklistEntry, BlocklistMatch, BlocklistMatchesResponse, BlocklistResponse, ErrorResponse, MobileApps,
+        PixelHost, PixelHostsResponse,
+    },
+};
+
+pub struct AdminAppState {
+    connection: Connection,
+    blocklist: SharedBlocklist,
+}
+
+pub fn router(blocklist: SharedBlocklist) -> Router {
+    let connection = sqlite::create_connection();
+    let state = Arc::new(Mutex::new(AdminAppState { connection, blocklist }));
+
+    Router::new()
+        .route(
+            "/blocklist",
+            get(get_blocklist)
+                .post(add_blocklist_entry)
+                .delete(remove_blocklist_entry),
+        )
+        .route("/blocklist/matches", get(get_blocklist_matches))
+        .route("/apps", get(get_mobile_apps).put(set_mobile_apps))
+        .route(
+            "/pixel-hosts",
+            get(get_pixel_hosts).post(add_pixel_host).delete(remove_pixel_host),
+        )
+        .with_state(state)
+}
+
+async fn get_blocklist(State(state): State<Arc<Mutex<AdminAppState>>>) -> impl IntoResponse {
+    let app_state = state.lock().await;
+    let blocklist = app_state.blocklist.read().unwrap_or_else(PoisonError::into_inner);
+
+    let entries = blocklist.entries().map(String::from).collect();
+
+    (StatusCode::OK, Json(BlocklistResponse { entries }))
+}
+
+async fn add_blocklist_entry(
+    State(state): State<Arc<Mutex<AdminAppState>>>,
+    Json(payload): Json<BlocklistEntry>,
+) -> impl IntoResponse {
+    let entry = payload.entry.trim();
+
+    if entry.is_empty() || entry.starts_with('#') || entry.contains(['\n', '\r']) {
+        let error = ErrorResponse::new("invalid_entry", "entry must be a single domain or url pattern");
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(error)).into_response();
+    }
+
+    let app_state = state.lock().await;
+    let mut blocklist = app_state.blocklist.write().unwrap_or_else(PoisonError::into_inner);
+
+    match blocklist.add(entry) {
+        Ok(true) => StatusCode::CREATED.into_response(),
+        Ok(false) => StatusCode::OK.into_response(),
+        Err(err) => {
+            println!("{:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn remove_blocklist_entry(
+    State(state): State<Arc<Mutex<AdminAppState>>>,
+    Json(payload): Json<BlocklistEntry>,
+) -> impl IntoResponse {
+    let app_state = state.lock().await;
+    let mut blocklist = app_state.blocklist.write().unwrap_or_else(PoisonError::into_inner);
+
+    match blocklist.remove(&payload.entry) {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => {
+            let error = ErrorResponse::new("not_found", "entry is not on the blocklist");
+            (StatusCode::NOT_FOUND, Json(error)).into_response()
+        }
+        Err(err) => {
+            println!("{:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn get_blocklist_matches(State(state): State<Arc<Mutex<AdminAppState>>>) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+
+    let links = match queries::find_active_links(&mut app_state.connection) {
+        Ok(links) => links,
+        Err(err) => {
+            println!("{:?}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let blocklist = app_state.blocklist.read().unwrap_or_else(PoisonError::into_inner);
+
+    let matches = links
+        .into_iter()
+        .filter_map(|link| {
+            let entry = blocklist.find_match(&link.url)?.to_owned();
+
+            Some(BlocklistMatch {
+                key: link.key,
+                domain: link.domain,
+                url: link.url,
+                user_id: link.user_id,
+                entry,
+            })
+        })
+        .collect();
+
+    (StatusCode::OK, Json(BlocklistMatchesResponse { matches })).into_response()
+}
+
+async fn get_pixel_hosts(State(state): State<Arc<Mutex<AdminAppState>>>) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+
+    match pixels::find_hosts(&mut app_state.connection) {
+        Ok(hosts) => (StatusCode::OK, Json(PixelHostsResponse { hosts })).into_response(),
+        Err(err) => {
+            println!("{:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn add_pixel_host(
+    State(state): State<Arc<Mutex<AdminAppState>>>,
+    Json(payload): Json<PixelHost>,
+) -> impl IntoResponse {
+    let host = payload.host.trim().to_ascii_lowercase();
+
+    if host.is_empty() || host.contains(['/', ':', ' ', '*']) {
+        let error = ErrorResponse::new("invalid_host", "host must be a plain domain name or IP address");
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(error)).into_response();
+    }
+
+    let mut app_state = state.lock().await;
+
+    match pixels::add_host(&mut app_state.connection, &host) {
+        Ok(true) => StatusCode::CREATED.into_response(),
+        Ok(false) => StatusCode::OK.into_response(),
+        Err(err) => {
+            println!("{:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Links keep their pixels from a removed host, but they are no longer loaded.
+async fn remove_pixel_host(
+    State(state): State<Arc<Mutex<AdminAppState>>>,
+    Json(payload): Json<PixelHost>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+
+    match pixels::remove_host(&mut app_state.connection, &payload.host.trim().to_ascii_lowercase()) {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => {
+            let error = ErrorResponse::new("not_found", "host is not on the allowlist");
+            (StatusCode::NOT_FOUND, Json(error)).into_response()
+        }
+        Err(err) => {
+            println!("{:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn get_mobile_apps(State(state): State<Arc<Mutex<AdminAppState>>>) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+
+    match links::find_mobile_apps(&mut app_state.connection) {
+        Ok(apps) => (StatusCode::OK, Json(MobileApps { apps })).into_response(),
+        Err(err) => {
+            println!("{:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn set_mobile_apps(
+    State(state): State<Arc<Mutex<AdminAppState>>>,
+    Json(mut payload): Json<MobileApps>,
+) -> impl IntoResponse {
+    for app in &mut payload.apps {
+        app.app_id = app.app_id.trim().to_owned();
+
+        if app.app_id.is_empty() {
+            let error = ErrorResponse::new("invalid_app_id", "app_id must not be empty");
+            return (StatusCode::UNPROCESSABLE_ENTITY, Json(error.with_field("apps.app_id"))).into_response();
+        }
+
+        if let Some(store_url) = &app.store_url
+            && !Url::parse(store_url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+        {
+            let error = ErrorResponse::new("invalid_store_url", "store_url must be an http(s) url");
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(error.with_field("apps.store_url")),
+            )
+                .into_response();
+        }
+
+        if app
+            .sha256_cert_fingerprints
+            .iter()
+            .any(|fingerprint| fingerprint.is_empty() || fingerprint.contains(','))
+        {
+            let error = ErrorResponse::new(
+                "invalid_fingerprint",
+                "fingerprints must be non-empty and without commas",
+            );
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(error.with_field("apps.sha256_cert_fingerprints")),
+            )
+                .into_response();
+        }
+    }
+
+    let mut app_state = state.lock().await;
+
+    if let Err(err) = queries::set_mobile_apps(&mut app_state.connection, &payload.apps) {
+        println!("{:?}", err);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    (StatusCode::OK, Json(payload)).into_response()
+}
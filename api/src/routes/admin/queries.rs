@@ -0,0 +1,37 @@
+use rusqlite::Connection;
+
+use crate::{
+    entities::{Link, MobileApp},
+    links::{link_from_row, LINK_COLUMNS},
+};
+
+pub fn find_active_links(connection: &mut Connection) -> Result<Vec<Link>, rusqlite::Error> {
+    let mut query = connection.prepare_cached(&format!("SELECT {LINK_COLUMNS} FROM urls WHERE deleted_at IS NULL"))?;
+
+    let links = query.query_map([], link_from_row)?.collect::<Result<Vec<Link>, _>>()?;
+
+    Ok(links)
+}
+
+/// Replaces the apps that are allowed to open deep links of the short domain.
+pub fn set_mobile_apps(connection: &mut Connection, apps: &[MobileApp]) -> Result<(), rusqlite::Error> {
+    let transaction = connection.transaction()?;
+
+    transaction.prepare_cached("DELETE FROM mobile_apps")?.execute([])?;
+
+    for app in apps {
+        transaction
+            .prepare_cached(
+                r"INSERT INTO mobile_apps (platform, app_id, store_url, sha256_cert_fingerprints)
+                  VALUES (?1, ?2, ?3, ?4)",
+            )?
+            .execute((
+                app.platform.as_str(),
+                &app.app_id,
+                &app.store_url,
+                app.sha256_cert_fingerprints.join(","),
+            ))?;
+    }
+
+    transaction.commit()
+}
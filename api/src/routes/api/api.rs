@@ -1,12 +1,584 @@
-use rusqlite::Connection;
+use rand::{distributions::Alphanumeric, Rng};
+use rusqlite::{params, types::Value, Connection, OptionalExtension};
+
+use crate::{
+    entities::{
+        ConversionGoal, DeepLink, DeviceRule, GeoRule, LanguageRule, Link, Pixel, RotationDestination, RotationMode,
+        ScheduleWindow, UrlRevision, UrlVariant,
+    },
+    links::{link_from_row, timestamp, LINK_COLUMNS},
+    routes::auth::auth::{hash_password, StoreError},
+    structs::{CreateShortUrl, LinkSort, PermanentRedirect, Profile, SortOrder, UpdateProfile, UpdateShortUrl},
+};
+
+// Long enough that goals can't be guessed from the outside
+const CONVERSION_TOKEN_LENGTH: usize = 24;
+
+// SQLITE_CONSTRAINT_PRIMARYKEY
+const DUPLICATE_KEY_CODE: i32 = 1555;
+const UNIQUE_CONSTRAINT_CODE: i32 = 2067;
 
 pub fn create_short_url(
     connection: &mut Connection,
     user_id: i64,
     key: &str,
-    value: &str,
-) -> Result<usize, rusqlite::Error> {
-    let mut insert = connection.prepare_cached("INSERT INTO urls (key, url, user_id) VALUES (?1, ?2, ?3)")?;
+    domain_id: Option<i64>,
+    payload: &CreateShortUrl,
+) -> Result<(), StoreError> {
+    let password_hash = match payload.password.as_deref().filter(|password| !password.is_empty()) {
+        Some(password) => Some(hash_password(password)?),
+        None => None,
+    };
+
+    let transaction = connection.transaction()?;
+
+    let link_id: i64 = transaction
+        .prepare_cached(
+            r"INSERT INTO urls (
+                key, url, user_id, expires_at, max_clicks, fallback_url, redirect_type,
+                forward_query, query_conflict, forward_path, active_from, active_until, password_hash,
+                visibility, require_signature, og_title, og_description, og_image, domain_id
+              ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)
+              RETURNING id",
+        )?
+        .query_row(
+            params![
+                key,
+                &payload.url,
+                user_id,
+                payload.expires_at.map(|expires_at| expires_at.unix_timestamp()),
+                payload.max_clicks,
+                &payload.fallback_url,
+                payload.redirect_type.unwrap_or_default().as_str(),
+                payload.forward_query.unwrap_or_default(),
+                payload.query_conflict.unwrap_or_default().as_str(),
+                payload.forward_path.unwrap_or_default(),
+                payload.active_from.map(|active_from| active_from.unix_timestamp()),
+                payload.active_until.map(|active_until| active_until.unix_timestamp()),
+                password_hash,
+                payload.visibility.unwrap_or_default().as_str(),
+                payload.require_signature.unwrap_or_default(),
+                &payload.og_title,
+                &payload.og_description,
+                &payload.og_image,
+                domain_id,
+            ],
+            |row| row.get("id"),
+        )?;
+
+    record_revision(&transaction, link_id, &payload.url, user_id)?;
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Stores a new destination for the link in the revision history and makes it the current one.
+fn record_revision(connection: &Connection, link_id: i64, url: &str, user_id: i64) -> Result<i64, rusqlite::Error> {
+    let revision_id: i64 = connection
+        .prepare_cached("INSERT INTO url_revisions (url_id, url, user_id) VALUES (?1, ?2, ?3) RETURNING id")?
+        .query_row((link_id, url, user_id), |row| row.get("id"))?;
+
+    connection
+        .prepare_cached("UPDATE urls SET url = ?2, revision_id = ?3 WHERE id = ?1")?
+        .execute((link_id, url, revision_id))?;
+
+    Ok(revision_id)
+}
+
+pub fn is_duplicate_key(err: &rusqlite::Error) -> bool {
+    matches!(err, rusqlite::Error::SqliteFailure(err, _) if err.extended_code == DUPLICATE_KEY_CODE)
+}
+
+/// Violations of `UNIQUE` constraints and indexes, as opposed to primary keys.
+pub fn is_unique_violation(err: &rusqlite::Error) -> bool {
+    matches!(err, rusqlite::Error::SqliteFailure(err, _) if err.extended_code == UNIQUE_CONSTRAINT_CODE)
+}
+
+/// Cursors are `<created_at>~<id>` or `<key>~<id>`, the id orders links with the same key on different domains.
+pub fn link_cursor(link: &Link, sort: LinkSort) -> String {
+    match sort {
+        LinkSort::CreatedAt => format!("{}~{}", link.created_at.unix_timestamp(), link.id),
+        LinkSort::Key => format!("{}~{}", link.key, link.id),
+    }
+}
+
+pub fn list_links(
+    connection: &mut Connection,
+    user_id: i64,
+    sort: LinkSort,
+    order: SortOrder,
+    cursor: Option<&str>,
+    limit: u32,
+) -> Result<Option<Vec<Link>>, rusqlite::Error> {
+    let (direction, comparison) = match order {
+        SortOrder::Asc => ("ASC", ">"),
+        SortOrder::Desc => ("DESC", "<"),
+    };
+
+    let mut params = vec![Value::Integer(user_id), Value::Integer(limit.into())];
+
+    let cursor_clause = match (sort, cursor) {
+        (_, None) => String::new(),
+        (sort, Some(cursor)) => {
+            let Some((position, id)) = cursor.split_once('~') else {
+                return Ok(None);
+            };
+
+            let Ok(id) = id.parse::<i64>() else {
+                return Ok(None);
+            };
+
+            match sort {
+                LinkSort::Key => params.push(Value::Text(position.to_owned())),
+                LinkSort::CreatedAt => match position.parse::<i64>() {
+                    Ok(created_at) => params.push(Value::Integer(created_at)),
+                    Err(_) => return Ok(None),
+                },
+            }
+
+            params.push(Value::Integer(id));
+            format!("AND ({}, id) {comparison} (?3, ?4)", sort_column(sort))
+        }
+    };
+
+    let order_clause = format!("{} {direction}, id {direction}", sort_column(sort));
+
+    let mut query = connection.prepare_cached(&format!(
+        r"SELECT {LINK_COLUMNS} FROM urls
+          WHERE user_id = ?1 AND deleted_at IS NULL {cursor_clause}
+          ORDER BY {order_clause}
+          LIMIT ?2"
+    ))?;
+
+    let links = query
+        .query_map(rusqlite::params_from_iter(params), link_from_row)?
+        .collect::<Result<Vec<Link>, _>>()?;
+
+    Ok(Some(links))
+}
+
+fn sort_column(sort: LinkSort) -> &'static str {
+    match sort {
+        LinkSort::CreatedAt => "unixepoch(created_at)",
+        LinkSort::Key => "key",
+    }
+}
+
+pub fn update_link(
+    connection: &mut Connection,
+    link: &Link,
+    user_id: i64,
+    payload: &UpdateShortUrl,
+) -> Result<(), StoreError> {
+    // An empty password removes the protection
+    let password_hash = match payload.password.as_deref() {
+        Some("") => Some(String::new()),
+        Some(password) => Some(hash_password(password)?),
+        None => None,
+    };
+
+    let transaction = connection.transaction()?;
+
+    // An expiry, click limit or activation bound of 0 and an empty fallback url remove them
+    transaction
+        .prepare_cached(
+            r"UPDATE urls SET
+                expires_at = IIF(?2 IS NULL, expires_at, NULLIF(?2, 0)),
+                max_clicks = IIF(?3 IS NULL, max_clicks, NULLIF(?3, 0)),
+                exhausted_at = IIF(
+                    clicks >= IIF(?3 IS NULL, max_clicks, NULLIF(?3, 0)),
+                    COALESCE(exhausted_at, unixepoch()),
+                    NULL
+                ),
+                fallback_url = IIF(?4 IS NULL, fallback_url, NULLIF(?4, '')),
+                redirect_type = COALESCE(?5, redirect_type),
+                forward_query = COALESCE(?6, forward_query),
+                query_conflict = COALESCE(?7, query_conflict),
+                forward_path = COALESCE(?8, forward_path),
+                active_from = IIF(?9 IS NULL, active_from, NULLIF(?9, 0)),
+                active_until = IIF(?10 IS NULL, active_until, NULLIF(?10, 0)),
+                password_hash = IIF(?11 IS NULL, password_hash, NULLIF(?11, '')),
+                visibility = COALESCE(?12, visibility),
+                og_title = IIF(?13 IS NULL, og_title, NULLIF(?13, '')),
+                og_description = IIF(?14 IS NULL, og_description, NULLIF(?14, '')),
+                og_image = IIF(?15 IS NULL, og_image, NULLIF(?15, '')),
+                require_signature = COALESCE(?16, require_signature)
+              WHERE id = ?1 AND deleted_at IS NULL",
+        )?
+        .execute((
+            link.id,
+            payload.expires_at.map(|expires_at| expires_at.unix_timestamp()),
+            payload.max_clicks,
+            &payload.fallback_url,
+            payload.redirect_type.map(|redirect_type| redirect_type.as_str()),
+            payload.forward_query,
+            payload.query_conflict.map(|query_conflict| query_conflict.as_str()),
+            payload.forward_path,
+            payload.active_from.map(|active_from| active_from.unix_timestamp()),
+            payload.active_until.map(|active_until| active_until.unix_timestamp()),
+            password_hash,
+            payload.visibility.map(|visibility| visibility.as_str()),
+            &payload.og_title,
+            &payload.og_description,
+            &payload.og_image,
+            payload.require_signature,
+        ))?;
+
+    if let Some(url) = &payload.url
+        && *url != link.url
+    {
+        record_revision(&transaction, link.id, url, user_id)?;
+    }
+
+    transaction.commit()?;
+
+    Ok(())
+}
+
+pub fn find_revisions(connection: &mut Connection, link_id: i64) -> Result<Vec<UrlRevision>, rusqlite::Error> {
+    let mut query = connection
+        .prepare_cached("SELECT id, url, user_id, created_at FROM url_revisions WHERE url_id = ?1 ORDER BY id DESC")?;
+
+    let revisions = query
+        .query_map([link_id], |row| {
+            Ok(UrlRevision {
+                id: row.get("id")?,
+                url: row.get("url")?,
+                user_id: row.get("user_id")?,
+                created_at: timestamp(row.get("created_at")?),
+            })
+        })?
+        .collect::<Result<Vec<UrlRevision>, _>>()?;
+
+    Ok(revisions)
+}
+
+/// Rolling back appends the old destination as a new revision, so the history itself is never rewritten.
+pub fn rollback_link(
+    connection: &mut Connection,
+    link_id: i64,
+    revision_id: i64,
+    user_id: i64,
+) -> Result<Option<i64>, rusqlite::Error> {
+    let transaction = connection.transaction()?;
+
+    let url: Option<String> = transaction
+        .prepare_cached("SELECT url FROM url_revisions WHERE url_id = ?1 AND id = ?2")?
+        .query_row((link_id, revision_id), |row| row.get("url"))
+        .optional()?;
+
+    let Some(url) = url else {
+        return Ok(None);
+    };
+
+    let revision_id = record_revision(&transaction, link_id, &url, user_id)?;
+    transaction.commit()?;
+
+    Ok(Some(revision_id))
+}
+
+/// Links on a custom domain are listed as `{hostname}/{key}`.
+pub fn find_permanent_redirects(
+    connection: &mut Connection,
+    user_id: i64,
+) -> Result<Vec<PermanentRedirect>, rusqlite::Error> {
+    let mut query = connection.prepare_cached(
+        r"SELECT urls.key, domains.hostname FROM urls
+            LEFT JOIN domains ON domains.id = urls.domain_id
+          WHERE urls.user_id = ?1 AND urls.deleted_at IS NULL AND urls.redirect_type IN ('301', '308')
+          ORDER BY domains.hostname, urls.key",
+    )?;
+
+    let redirects = query
+        .query_map([user_id], |row| {
+            Ok(PermanentRedirect {
+                key: row.get("key")?,
+                domain: row.get("hostname")?,
+            })
+        })?
+        .collect::<Result<Vec<PermanentRedirect>, _>>()?;
+
+    Ok(redirects)
+}
+
+/// Replaces all device rules of a link.
+pub fn set_device_rules(
+    connection: &mut Connection,
+    link_id: i64,
+    rules: &[DeviceRule],
+) -> Result<(), rusqlite::Error> {
+    let transaction = connection.transaction()?;
+
+    transaction
+        .prepare_cached("DELETE FROM device_rules WHERE url_id = ?1")?
+        .execute([link_id])?;
+
+    for rule in rules {
+        transaction
+            .prepare_cached("INSERT INTO device_rules (url_id, device, url) VALUES (?1, ?2, ?3)")?
+            .execute((link_id, rule.device.as_str(), &rule.url))?;
+    }
+
+    transaction.commit()
+}
+
+/// Replaces all geo rules of a link, rules are evaluated in the given order.
+pub fn set_geo_rules(connection: &mut Connection, link_id: i64, rules: &[GeoRule]) -> Result<(), rusqlite::Error> {
+    let transaction = connection.transaction()?;
+
+    transaction
+        .prepare_cached("DELETE FROM geo_rules WHERE url_id = ?1")?
+        .execute([link_id])?;
+
+    for (position, rule) in rules.iter().enumerate() {
+        transaction
+            .prepare_cached(
+                r"INSERT INTO geo_rules (url_id, position, continent, country, region, url)
+                  VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?
+            .execute((
+                link_id,
+                position,
+                &rule.continent,
+                &rule.country,
+                &rule.region,
+                &rule.url,
+            ))?;
+    }
+
+    transaction.commit()
+}
+
+/// Replaces all language rules of a link.
+pub fn set_language_rules(
+    connection: &mut Connection,
+    link_id: i64,
+    rules: &[LanguageRule],
+) -> Result<(), rusqlite::Error> {
+    let transaction = connection.transaction()?;
+
+    transaction
+        .prepare_cached("DELETE FROM language_rules WHERE url_id = ?1")?
+        .execute([link_id])?;
+
+    for rule in rules {
+        transaction
+            .prepare_cached("INSERT INTO language_rules (url_id, language, url) VALUES (?1, ?2, ?3)")?
+            .execute((link_id, &rule.language, &rule.url))?;
+    }
+
+    transaction.commit()
+}
+
+/// Replaces all pixels of a link together with the delay before the visitor is sent on.
+pub fn set_pixels(
+    connection: &mut Connection,
+    link_id: i64,
+    pixels: &[Pixel],
+    delay_ms: u32,
+) -> Result<(), rusqlite::Error> {
+    let transaction = connection.transaction()?;
+
+    transaction
+        .prepare_cached("DELETE FROM url_pixels WHERE url_id = ?1")?
+        .execute([link_id])?;
+
+    for pixel in pixels {
+        transaction
+            .prepare_cached("INSERT INTO url_pixels (url_id, kind, url) VALUES (?1, ?2, ?3)")?
+            .execute((link_id, pixel.kind.as_str(), &pixel.url))?;
+    }
+
+    transaction
+        .prepare_cached("UPDATE urls SET pixel_delay_ms = ?2 WHERE id = ?1")?
+        .execute((link_id, delay_ms))?;
+
+    transaction.commit()
+}
+
+pub fn set_deep_link(connection: &mut Connection, link_id: i64, deep_link: &DeepLink) -> Result<(), rusqlite::Error> {
+    connection
+        .prepare_cached(
+            r"INSERT INTO deep_links (url_id, app_url, ios_store_url, android_store_url) VALUES (?1, ?2, ?3, ?4)
+              ON CONFLICT (url_id) DO UPDATE SET
+                app_url = excluded.app_url,
+                ios_store_url = excluded.ios_store_url,
+                android_store_url = excluded.android_store_url",
+        )?
+        .execute((
+            link_id,
+            &deep_link.app_url,
+            &deep_link.ios_store_url,
+            &deep_link.android_store_url,
+        ))
+        .map(|_| ())
+}
+
+pub fn delete_deep_link(connection: &mut Connection, link_id: i64) -> Result<bool, rusqlite::Error> {
+    connection
+        .prepare_cached("DELETE FROM deep_links WHERE url_id = ?1")?
+        .execute([link_id])
+        .map(|deleted| deleted > 0)
+}
+
+/// Replaces the rotating destinations of a link. Destinations that stay keep their hit counts, so changing the
+/// order or adding a mirror doesn't reset the statistics.
+pub fn set_rotation(
+    connection: &mut Connection,
+    link_id: i64,
+    mode: Option<RotationMode>,
+    destinations: &[RotationDestination],
+) -> Result<(), rusqlite::Error> {
+    let transaction = connection.transaction()?;
+
+    transaction
+        .prepare_cached("UPDATE url_rotations SET position = NULL WHERE url_id = ?1")?
+        .execute([link_id])?;
+
+    for (position, destination) in destinations.iter().enumerate() {
+        transaction
+            .prepare_cached(
+                r"INSERT INTO url_rotations (url_id, url, position) VALUES (?1, ?2, ?3)
+                  ON CONFLICT (url_id, url) DO UPDATE SET position = excluded.position",
+            )?
+            .execute((link_id, &destination.url, position))?;
+    }
+
+    transaction
+        .prepare_cached("DELETE FROM url_rotations WHERE url_id = ?1 AND position IS NULL")?
+        .execute([link_id])?;
+
+    transaction
+        .prepare_cached("UPDATE urls SET rotation = ?2 WHERE id = ?1")?
+        .execute((link_id, mode.map(|mode| mode.as_str())))?;
+
+    transaction.commit()
+}
+
+/// Replaces all variants of a link. Changing weights or the order of variants reassigns some returning visitors.
+pub fn set_variants(connection: &mut Connection, link_id: i64, variants: &[UrlVariant]) -> Result<(), rusqlite::Error> {
+    let transaction = connection.transaction()?;
+
+    transaction
+        .prepare_cached("DELETE FROM url_variants WHERE url_id = ?1")?
+        .execute([link_id])?;
+
+    for variant in variants {
+        transaction
+            .prepare_cached("INSERT INTO url_variants (url_id, name, url, weight) VALUES (?1, ?2, ?3, ?4)")?
+            .execute((link_id, &variant.name, &variant.url, variant.weight))?;
+    }
+
+    transaction.commit()
+}
+
+/// Replaces all scheduled destinations of a link.
+pub fn set_schedule(
+    connection: &mut Connection,
+    link_id: i64,
+    windows: &[ScheduleWindow],
+) -> Result<(), rusqlite::Error> {
+    let transaction = connection.transaction()?;
+
+    transaction
+        .prepare_cached("DELETE FROM url_schedules WHERE url_id = ?1")?
+        .execute([link_id])?;
+
+    for window in windows {
+        transaction
+            .prepare_cached(
+                "INSERT INTO url_schedules (url_id, starts_at, ends_at, local_time, url) VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?
+            .execute((
+                link_id,
+                window.starts_at.map(|starts_at| starts_at.unix_timestamp()),
+                window.ends_at.map(|ends_at| ends_at.unix_timestamp()),
+                window.local_time,
+                &window.url,
+            ))?;
+    }
+
+    transaction.commit()
+}
+
+/// Links are only marked as deleted so their key stays reserved and metrics remain attributable.
+pub fn delete_link(connection: &mut Connection, link_id: i64) -> Result<usize, rusqlite::Error> {
+    let mut update = connection.prepare_cached("UPDATE urls SET deleted_at = unixepoch() WHERE id = ?1")?;
+
+    update.execute([link_id])
+}
+
+pub fn find_profile(connection: &mut Connection, user_id: i64) -> Result<Profile, rusqlite::Error> {
+    connection
+        .prepare_cached("SELECT display_name, public_profile FROM users WHERE id = ?1")?
+        .query_row([user_id], |row| {
+            Ok(Profile {
+                display_name: row.get("display_name")?,
+                public_profile: row.get("public_profile")?,
+            })
+        })
+}
+
+/// An empty display name removes it.
+pub fn update_profile(
+    connection: &mut Connection,
+    user_id: i64,
+    payload: &UpdateProfile,
+) -> Result<(), rusqlite::Error> {
+    connection
+        .prepare_cached(
+            r"UPDATE users SET
+                display_name = IIF(?2 IS NULL, display_name, NULLIF(?2, '')),
+                public_profile = COALESCE(?3, public_profile)
+              WHERE id = ?1",
+        )?
+        .execute((
+            user_id,
+            payload.display_name.as_deref().map(str::trim),
+            payload.public_profile,
+        ))
+        .map(|_| ())
+}
+
+pub fn find_conversion_goals(
+    connection: &mut Connection,
+    user_id: i64,
+) -> Result<Vec<ConversionGoal>, rusqlite::Error> {
+    let mut query = connection.prepare_cached(
+        "SELECT token, name, created_at FROM conversion_goals WHERE user_id = ?1 ORDER BY created_at",
+    )?;
+
+    let goals = query
+        .query_map([user_id], |row| {
+            Ok(ConversionGoal {
+                token: row.get("token")?,
+                name: row.get("name")?,
+                created_at: timestamp(row.get("created_at")?),
+            })
+        })?
+        .collect::<Result<Vec<ConversionGoal>, _>>()?;
+
+    Ok(goals)
+}
+
+pub fn create_conversion_goal(
+    connection: &mut Connection,
+    user_id: i64,
+    name: &str,
+) -> Result<ConversionGoal, rusqlite::Error> {
+    let token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(CONVERSION_TOKEN_LENGTH)
+        .map(char::from)
+        .collect();
 
-    insert.execute((key, value, user_id))
+    connection
+        .prepare_cached("INSERT INTO conversion_goals (token, user_id, name) VALUES (?1, ?2, ?3) RETURNING created_at")?
+        .query_row((&token, user_id, name), |row| {
+            Ok(ConversionGoal {
+                token: token.clone(),
+                name: name.to_owned(),
+                created_at: timestamp(row.get("created_at")?),
+            })
+        })
 }
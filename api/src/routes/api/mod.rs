@@ -1,61 +1,1641 @@
 pub mod api;
 
-use std::sync::Arc;
+use std::{
+    net::IpAddr,
+    sync::{Arc, PoisonError},
+};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header::CONTENT_TYPE, HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::{get, patch, post},
+    Extension, Json, Router,
+};
+use rusqlite::Connection;
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+use url::Url;
+
+use crate::{
+    blocklist::SharedBlocklist,
+    branding,
+    dns::TxtResolver,
+    domains,
+    entities::{
+        ConversionMetrics, DeepLink, Domain, LanguageMetrics, Link, MetricsWithinInterval, User, VariantMetrics,
+    },
+    geo,
+    headers::TypedHeaderValues,
+    id::{generate_id, validate_alias},
+    language, links,
+    middleware::auth::UserSession,
+    pixels, qr,
+    routes::auth::auth::StoreError,
+    signing::LinkSigner,
+    sqlite,
+    structs::{
+        ConversionGoalsResponse, ConversionMetricsRequest, ConversionMetricsResponse, CreateConversionGoal,
+        CreateDomain, CreateShortUrl, DeviceRules, DomainResponse, DomainVerification, DomainsResponse, ErrorResponse,
+        GeoRules, HistoryResponse, LanguageMetricsRequest, LanguageMetricsResponse, LanguageRules, LinkDomain,
+        LinksRequest, LinksResponse, MetricsRequest, MetricsResponse, Pixels, QrCodeRequest, QrErrorCorrection,
+        QrFormat, Rollback, Rotation, Schedule, ShortUrlCreated, SignLink, SignedLink, UpdateBrandedPages,
+        UpdateProfile, UpdateShortUrl, UrlVariants, VariantMetricsResponse,
+    },
+    unfurl,
+    validation::UrlPolicy,
+};
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 100;
+const MAX_DISPLAY_NAME_LENGTH: usize = 64;
+const MAX_PIXEL_DELAY_MS: u32 = 10_000;
+const DEFAULT_ATTRIBUTION_WINDOW_HOURS: u32 = 7 * 24;
+const MAX_ATTRIBUTION_WINDOW_HOURS: u32 = 90 * 24;
+const DEFAULT_QR_SIZE: u32 = 512;
+const MIN_QR_SIZE: u32 = 64;
+const MAX_QR_SIZE: u32 = 4096;
+const DEFAULT_QR_MARGIN: u32 = 4;
+const MAX_QR_MARGIN: u32 = 32;
+
+pub struct ApiAppState {
+    pg_conn: deadpool_postgres::Object,
+    connection: Connection,
+    url_policy: UrlPolicy,
+    blocklist: SharedBlocklist,
+    link_signer: Arc<LinkSigner>,
+    http_client: reqwest::Client,
+    resolver: TxtResolver,
+}
+
+pub fn router(pg_conn: deadpool_postgres::Object, blocklist: SharedBlocklist, link_signer: Arc<LinkSigner>) -> Router {
+    let connection = sqlite::create_connection();
+    let state = Arc::new(Mutex::new(ApiAppState {
+        connection,
+        pg_conn,
+        url_policy: UrlPolicy::from_env(),
+        blocklist,
+        link_signer,
+        http_client: unfurl::client(),
+        resolver: TxtResolver::from_env(),
+    }));
+
+    Router::new()
+        .route("/create-short-url", post(create_short_url))
+        .route("/metrics", get(get_metrics))
+        .route("/metrics/languages", get(get_language_metrics))
+        .route("/metrics/conversions", get(get_conversion_metrics))
+        .route(
+            "/conversion-goals",
+            get(list_conversion_goals).post(create_conversion_goal),
+        )
+        .route("/profile", get(get_profile).patch(update_profile))
+        .route("/pages", get(get_pages).patch(update_pages))
+        .route("/domains", get(list_domains).post(create_domain))
+        .route("/domains/{hostname}", patch(update_domain).delete(delete_domain))
+        .route("/domains/{hostname}/verify", post(verify_domain))
+        .route("/links", get(list_links))
+        .route("/links/{key}", get(get_link).patch(update_link).delete(delete_link))
+        .route("/links/{key}/history", get(get_link_history))
+        .route("/links/{key}/rollback", post(rollback_link))
+        .route("/links/{key}/device-rules", get(get_device_rules).put(set_device_rules))
+        .route("/links/{key}/geo-rules", get(get_geo_rules).put(set_geo_rules))
+        .route("/links/{key}/variants", get(get_variants).put(set_variants))
+        .route("/links/{key}/variants/metrics", get(get_variant_metrics))
+        .route("/links/{key}/rotation", get(get_rotation).put(set_rotation))
+        .route("/links/{key}/qr", get(get_qr_code))
+        .route(
+            "/links/{key}/deep-link",
+            get(get_deep_link).put(set_deep_link).delete(delete_deep_link),
+        )
+        .route("/links/{key}/schedule", get(get_schedule).put(set_schedule))
+        .route("/links/{key}/sign", post(sign_link))
+        .route("/links/{key}/pixels", get(get_pixels).put(set_pixels))
+        .route(
+            "/links/{key}/language-rules",
+            get(get_language_rules).put(set_language_rules),
+        )
+        .with_state(state)
+}
+
+async fn create_short_url(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Json(mut payload): Json<CreateShortUrl>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let app_state = &mut *app_state;
+
+    if let Err(err) = check_destination(app_state, "url", &mut payload.url) {
+        return err.into_response();
+    }
+
+    if let Some(fallback_url) = &mut payload.fallback_url
+        && let Err(err) = check_destination(app_state, "fallback_url", fallback_url)
+    {
+        return err.into_response();
+    }
+
+    if let Some(og_image) = payload.og_image.as_mut().filter(|og_image| !og_image.is_empty())
+        && let Err(err) = check_destination(app_state, "og_image", og_image)
+    {
+        return err.into_response();
+    }
+
+    let connection = &mut app_state.connection;
+
+    let domain = match payload.domain.as_deref() {
+        Some(hostname) => match domains::find_owned(connection, session.user.id, &hostname.to_ascii_lowercase()) {
+            Ok(Some(domain)) if domain.verified_at.is_some() => Some(domain),
+            Ok(Some(_)) => {
+                let error = ErrorResponse::new("domain_not_verified", format!("domain '{hostname}' is not verified"));
+                return (StatusCode::UNPROCESSABLE_ENTITY, Json(error.with_field("domain"))).into_response();
+            }
+            Ok(None) => {
+                let error = ErrorResponse::new("unknown_domain", format!("domain '{hostname}' does not exist"));
+                return (StatusCode::UNPROCESSABLE_ENTITY, Json(error.with_field("domain"))).into_response();
+            }
+            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        },
+        None => None,
+    };
+
+    let domain_id = domain.as_ref().map(|domain| domain.id);
+    let domain = domain.map(|domain| domain.hostname);
+
+    if let Some(alias) = &payload.alias {
+        if let Err(err) = validate_alias(alias) {
+            let error = ErrorResponse::new("invalid_alias", err.to_string());
+            return (StatusCode::UNPROCESSABLE_ENTITY, Json(error)).into_response();
+        }
+
+        return match api::create_short_url(connection, session.user.id, alias, domain_id, &payload) {
+            Ok(_) => {
+                let created = ShortUrlCreated {
+                    id: alias.clone(),
+                    domain,
+                };
+                (StatusCode::CREATED, Json(created)).into_response()
+            }
+            Err(StoreError::Database(err)) if api::is_unique_violation(&err) => {
+                let error = ErrorResponse::new("alias_taken", format!("alias '{alias}' is already in use"));
+                (StatusCode::CONFLICT, Json(error)).into_response()
+            }
+            Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        };
+    }
+
+    let mut retries = 0;
+
+    while retries < 5 {
+        let id = generate_id();
+        match api::create_short_url(connection, session.user.id, &id, domain_id, &payload) {
+            Ok(_) => return (StatusCode::CREATED, Json(ShortUrlCreated { id, domain })).into_response(),
+            Err(StoreError::Database(err)) if api::is_unique_violation(&err) => retries += 1,
+            Err(_) => break,
+        }
+    }
+
+    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+}
+
+async fn list_links(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Query(params): Query<LinksRequest>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    let query = api::list_links(
+        &mut app_state.connection,
+        session.user.id,
+        params.sort,
+        params.order,
+        params.cursor.as_deref(),
+        limit + 1,
+    );
+
+    let mut links = match query {
+        Ok(Some(links)) => links,
+        Ok(None) => {
+            let error = ErrorResponse::new("invalid_cursor", "cursor is malformed");
+            return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+        }
+        Err(err) => {
+            println!("{:?}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let next_cursor = if links.len() > limit as usize {
+        links.truncate(limit as usize);
+        links.last().map(|link| api::link_cursor(link, params.sort))
+    } else {
+        None
+    };
+
+    (StatusCode::OK, Json(LinksResponse { links, next_cursor })).into_response()
+}
+
+async fn get_link(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+
+    match find_owned_link(&mut app_state.connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => (StatusCode::OK, Json(link)).into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+async fn update_link(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+    Json(mut payload): Json<UpdateShortUrl>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let app_state = &mut *app_state;
+
+    if let Some(url) = &mut payload.url
+        && let Err(err) = check_destination(app_state, "url", url)
+    {
+        return err.into_response();
+    }
+
+    if let Some(fallback_url) = payload
+        .fallback_url
+        .as_mut()
+        .filter(|fallback_url| !fallback_url.is_empty())
+        && let Err(err) = check_destination(app_state, "fallback_url", fallback_url)
+    {
+        return err.into_response();
+    }
+
+    if let Some(og_image) = payload.og_image.as_mut().filter(|og_image| !og_image.is_empty())
+        && let Err(err) = check_destination(app_state, "og_image", og_image)
+    {
+        return err.into_response();
+    }
+
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    if api::update_link(connection, &link, session.user.id, &payload).is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => (StatusCode::OK, Json(link)).into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+async fn delete_link(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    match api::delete_link(connection, link.id) {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn get_link_history(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    match api::find_revisions(connection, link.id) {
+        Ok(revisions) => (StatusCode::OK, Json(HistoryResponse { revisions })).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn rollback_link(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+    Json(payload): Json<Rollback>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    match api::rollback_link(connection, link.id, payload.revision_id, session.user.id) {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            let error = ErrorResponse::new("not_found", format!("revision {} does not exist", payload.revision_id));
+            return (StatusCode::NOT_FOUND, Json(error)).into_response();
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => (StatusCode::OK, Json(link)).into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+async fn get_device_rules(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    match links::find_device_rules(connection, link.id) {
+        Ok(rules) => (StatusCode::OK, Json(DeviceRules { rules })).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn set_device_rules(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+    Json(mut payload): Json<DeviceRules>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let app_state = &mut *app_state;
+
+    for rule in &mut payload.rules {
+        if let Err(err) = check_destination(app_state, "rules.url", &mut rule.url) {
+            return err.into_response();
+        }
+    }
+
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    match api::set_device_rules(connection, link.id, &payload.rules) {
+        Ok(()) => (StatusCode::OK, Json(payload)).into_response(),
+        Err(err) if api::is_duplicate_key(&err) => {
+            let error = ErrorResponse::new("duplicate_device", "each device may only have one rule");
+            (StatusCode::UNPROCESSABLE_ENTITY, Json(error.with_field("rules.device"))).into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn get_geo_rules(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    match links::find_geo_rules(connection, link.id) {
+        Ok(rules) => (StatusCode::OK, Json(GeoRules { rules })).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn set_geo_rules(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+    Json(mut payload): Json<GeoRules>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let app_state = &mut *app_state;
+
+    for rule in &mut payload.rules {
+        if let Err(err) = check_destination(app_state, "rules.url", &mut rule.url) {
+            return err.into_response();
+        }
+
+        rule.continent = rule.continent.as_deref().map(str::to_ascii_uppercase);
+        rule.country = rule.country.as_deref().map(str::to_ascii_uppercase);
+
+        if let Some(continent) = &rule.continent
+            && !geo::CONTINENTS.contains(&continent.as_str())
+        {
+            let error = ErrorResponse::new("invalid_continent", format!("unknown continent code '{continent}'"));
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(error.with_field("rules.continent")),
+            )
+                .into_response();
+        }
+
+        if let Some(country) = &rule.country
+            && (country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphabetic()))
+        {
+            let error = ErrorResponse::new("invalid_country", "country must be an ISO 3166-1 alpha-2 code");
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(error.with_field("rules.country")),
+            )
+                .into_response();
+        }
+    }
+
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    if api::set_geo_rules(connection, link.id, &payload.rules).is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    match links::find_geo_rules(connection, link.id) {
+        Ok(rules) => (StatusCode::OK, Json(GeoRules { rules })).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn get_variants(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    match links::find_variants(connection, link.id) {
+        Ok(variants) => (StatusCode::OK, Json(UrlVariants { variants })).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn set_variants(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+    Json(mut payload): Json<UrlVariants>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let app_state = &mut *app_state;
+
+    for variant in &mut payload.variants {
+        if let Err(err) = check_destination(app_state, "variants.url", &mut variant.url) {
+            return err.into_response();
+        }
+
+        if variant.weight == 0 {
+            let error = ErrorResponse::new("invalid_weight", "weight must be greater than zero");
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(error.with_field("variants.weight")),
+            )
+                .into_response();
+        }
+    }
+
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    if api::set_variants(connection, link.id, &payload.variants).is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    match links::find_variants(connection, link.id) {
+        Ok(variants) => (StatusCode::OK, Json(UrlVariants { variants })).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Renders a QR code for printing. It encodes `/q/{key}` rather than the link itself, so scans show up as their own
+/// source in the metrics.
+async fn get_qr_code(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+    headers: HeaderMap,
+    Query(params): Query<QrCodeRequest>,
+) -> impl IntoResponse {
+    let size = params.size.unwrap_or(DEFAULT_QR_SIZE);
+
+    if !(MIN_QR_SIZE..=MAX_QR_SIZE).contains(&size) {
+        let error = ErrorResponse::new(
+            "invalid_size",
+            format!("size must be between {MIN_QR_SIZE} and {MAX_QR_SIZE} pixels"),
+        );
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(error.with_field("size"))).into_response();
+    }
+
+    let margin = params.margin.unwrap_or(DEFAULT_QR_MARGIN);
+
+    if margin > MAX_QR_MARGIN {
+        let error = ErrorResponse::new(
+            "invalid_margin",
+            format!("margin must be at most {MAX_QR_MARGIN} modules"),
+        );
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(error.with_field("margin"))).into_response();
+    }
+
+    let foreground = match params.foreground.as_deref().map(qr::parse_color) {
+        Some(Some(color)) => color,
+        Some(None) => {
+            let error = ErrorResponse::new("invalid_color", "colors must be given as #rrggbb");
+            return (StatusCode::UNPROCESSABLE_ENTITY, Json(error.with_field("foreground"))).into_response();
+        }
+        None => [0, 0, 0],
+    };
+
+    let background = match params.background.as_deref().map(qr::parse_color) {
+        Some(Some(color)) => color,
+        Some(None) => {
+            let error = ErrorResponse::new("invalid_color", "colors must be given as #rrggbb");
+            return (StatusCode::UNPROCESSABLE_ENTITY, Json(error.with_field("background"))).into_response();
+        }
+        None => [255, 255, 255],
+    };
+
+    // The logo hides part of the code, which only scans if error correction can restore it
+    let error_correction = match (params.error_correction, &params.logo) {
+        (Some(QrErrorCorrection::Low), Some(_)) => {
+            let error = ErrorResponse::new(
+                "invalid_error_correction",
+                "a logo needs at least medium error correction",
+            );
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(error.with_field("error_correction")),
+            )
+                .into_response();
+        }
+        (Some(error_correction), _) => error_correction,
+        (None, Some(_)) => QrErrorCorrection::High,
+        (None, None) => QrErrorCorrection::Medium,
+    };
+
+    let mut app_state = state.lock().await;
+
+    let link = match find_owned_link(&mut app_state.connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    let client = app_state.http_client.clone();
+    drop(app_state);
+
+    let Some(url) = short_url(&link, &headers, &format!("q/{key}")) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let code = match qr::encode(&url, error_correction) {
+        Ok(code) => code,
+        Err(err) => {
+            println!("{:?}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    if size < qr::total_modules(&code, margin) {
+        let error = ErrorResponse::new("invalid_size", "size is too small to fit a pixel per module");
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(error.with_field("size"))).into_response();
+    }
+
+    let logo = match &params.logo {
+        Some(logo_url) => match qr::fetch_logo(&client, logo_url).await {
+            Some(logo) => Some(logo),
+            None => {
+                let error = ErrorResponse::new("invalid_logo", "logo must be a publicly reachable PNG image");
+                return (StatusCode::UNPROCESSABLE_ENTITY, Json(error.with_field("logo"))).into_response();
+            }
+        },
+        None => None,
+    };
+
+    let style = qr::Style {
+        size,
+        margin,
+        foreground,
+        background,
+    };
+
+    match params.format.unwrap_or(QrFormat::Png) {
+        QrFormat::Png => match qr::render_png(&code, &style, logo.as_ref()) {
+            Ok(png) => ([(CONTENT_TYPE, "image/png")], png).into_response(),
+            Err(err) => {
+                println!("{:?}", err);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        },
+        QrFormat::Svg => {
+            let svg = qr::render_svg(&code, &style, logo.as_ref());
+            ([(CONTENT_TYPE, "image/svg+xml")], svg).into_response()
+        }
+    }
+}
+
+async fn get_deep_link(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    match links::find_deep_link(connection, link.id) {
+        Ok(Some(deep_link)) => (StatusCode::OK, Json(deep_link)).into_response(),
+        Ok(None) => {
+            let error = ErrorResponse::new("not_found", format!("link '{key}' is not a deep link"));
+            (StatusCode::NOT_FOUND, Json(error)).into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn set_deep_link(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+    Json(mut payload): Json<DeepLink>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let app_state = &mut *app_state;
+
+    // Custom schemes are the point of deep links, so only schemes that run code in the page are refused
+    let is_app_url = Url::parse(&payload.app_url)
+        .is_ok_and(|url| !matches!(url.scheme(), "javascript" | "data" | "vbscript" | "file" | "blob"));
+
+    if !is_app_url {
+        let error = ErrorResponse::new("invalid_app_url", "app_url must be a custom scheme or universal link");
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(error.with_field("app_url"))).into_response();
+    }
+
+    if let Some(ios_store_url) = &mut payload.ios_store_url
+        && let Err(err) = check_destination(app_state, "ios_store_url", ios_store_url)
+    {
+        return err.into_response();
+    }
+
+    if let Some(android_store_url) = &mut payload.android_store_url
+        && let Err(err) = check_destination(app_state, "android_store_url", android_store_url)
+    {
+        return err.into_response();
+    }
+
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    match api::set_deep_link(connection, link.id, &payload) {
+        Ok(()) => (StatusCode::OK, Json(payload)).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Turns the deep link back into a plain link.
+async fn delete_deep_link(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    match api::delete_deep_link(connection, link.id) {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => {
+            let error = ErrorResponse::new("not_found", format!("link '{key}' is not a deep link"));
+            (StatusCode::NOT_FOUND, Json(error)).into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn get_rotation(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    match links::find_rotation(connection, link.id) {
+        Ok(destinations) => (
+            StatusCode::OK,
+            Json(Rotation {
+                mode: link.rotation,
+                destinations,
+            }),
+        )
+            .into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn set_rotation(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+    Json(mut payload): Json<Rotation>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let app_state = &mut *app_state;
+
+    for destination in &mut payload.destinations {
+        if let Err(err) = check_destination(app_state, "destinations.url", &mut destination.url) {
+            return err.into_response();
+        }
+    }
+
+    let mut urls: Vec<&str> = payload
+        .destinations
+        .iter()
+        .map(|destination| destination.url.as_str())
+        .collect();
+    urls.sort_unstable();
+
+    if urls.windows(2).any(|pair| pair[0] == pair[1]) {
+        let error = ErrorResponse::new("duplicate_destination", "each destination may only be listed once");
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(error.with_field("destinations.url")),
+        )
+            .into_response();
+    }
+
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    if api::set_rotation(connection, link.id, payload.mode, &payload.destinations).is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    match links::find_rotation(connection, link.id) {
+        Ok(destinations) => (
+            StatusCode::OK,
+            Json(Rotation {
+                mode: payload.mode,
+                destinations,
+            }),
+        )
+            .into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn get_variant_metrics(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+
+    let link = match find_owned_link(&mut app_state.connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    let variants = match links::find_variants(&mut app_state.connection, link.id) {
+        Ok(variants) => variants,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let query = app_state
+        .pg_conn
+        .query(
+            r"
+          SELECT
+            variant_id,
+            count(*) AS count,
+            distinct_count(approx_count_distinct(visitor_id)) AS unique_count
+          FROM
+            metrics
+          WHERE
+            user_id = $1 AND key = $2 AND domain IS NOT DISTINCT FROM $3
+          GROUP BY
+            variant_id
+          ",
+            &[&session.user.id, &link.key, &link.domain],
+        )
+        .await;
+
+    let rows = match query {
+        Ok(rows) => rows,
+        Err(err) => {
+            println!("{:?}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let metrics = rows
+        .iter()
+        .map(|row| {
+            let variant_id: Option<i64> = row.get("variant_id");
+            let variant = variants.iter().find(|variant| Some(variant.id) == variant_id);
+
+            VariantMetrics {
+                variant_id,
+                name: variant.and_then(|variant| variant.name.clone()),
+                url: variant.map(|variant| variant.url.clone()),
+                count: row.get("count"),
+                unique_count: row.get("unique_count"),
+            }
+        })
+        .collect();
+
+    (StatusCode::OK, Json(VariantMetricsResponse { variants: metrics })).into_response()
+}
+
+async fn get_schedule(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    match links::find_schedule(connection, link.id) {
+        Ok(windows) => (StatusCode::OK, Json(Schedule { windows })).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn set_schedule(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+    Json(mut payload): Json<Schedule>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let app_state = &mut *app_state;
+
+    for window in &mut payload.windows {
+        if let Err(err) = check_destination(app_state, "windows.url", &mut window.url) {
+            return err.into_response();
+        }
+
+        if let (Some(starts_at), Some(ends_at)) = (window.starts_at, window.ends_at)
+            && ends_at <= starts_at
+        {
+            let error = ErrorResponse::new("invalid_window", "window must end after it starts");
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(error.with_field("windows.ends_at")),
+            )
+                .into_response();
+        }
+    }
+
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    if api::set_schedule(connection, link.id, &payload.windows).is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    match links::find_schedule(connection, link.id) {
+        Ok(windows) => (StatusCode::OK, Json(Schedule { windows })).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn get_language_rules(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    match links::find_language_rules(connection, link.id) {
+        Ok(rules) => (StatusCode::OK, Json(LanguageRules { rules })).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn set_language_rules(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+    Json(mut payload): Json<LanguageRules>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let app_state = &mut *app_state;
+
+    for rule in &mut payload.rules {
+        if let Err(err) = check_destination(app_state, "rules.url", &mut rule.url) {
+            return err.into_response();
+        }
+
+        rule.language = rule.language.trim().to_ascii_lowercase();
+
+        if !language::is_valid_tag(&rule.language) {
+            let error = ErrorResponse::new("invalid_language", format!("'{}' is not a language tag", rule.language));
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(error.with_field("rules.language")),
+            )
+                .into_response();
+        }
+    }
+
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    match api::set_language_rules(connection, link.id, &payload.rules) {
+        Ok(()) => (StatusCode::OK, Json(payload)).into_response(),
+        Err(err) if api::is_duplicate_key(&err) => {
+            let error = ErrorResponse::new("duplicate_language", "each language may only have one rule");
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(error.with_field("rules.language")),
+            )
+                .into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Replaces `url` with its normalized form or describes why it can't be used as a redirect target.
+fn check_destination(
+    app_state: &ApiAppState,
+    field: &'static str,
+    url: &mut String,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let normalized = match app_state.url_policy.normalize(url) {
+        Ok(normalized) => normalized,
+        Err(err) => {
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(ErrorResponse::new(err.code(), err.to_string()).with_field(field)),
+            ))
+        }
+    };
+
+    if app_state
+        .blocklist
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .find_match(&normalized)
+        .is_some()
+    {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ErrorResponse::new("url_blocked", "url is on the blocklist").with_field(field)),
+        ));
+    }
+
+    *url = normalized;
+
+    Ok(())
+}
+
+async fn get_language_metrics(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Query(params): Query<LanguageMetricsRequest>,
+) -> impl IntoResponse {
+    let app_state = state.lock().await;
+    let domain = params.domain.as_deref().map(str::to_ascii_lowercase);
+
+    let query = app_state
+        .pg_conn
+        .query(
+            r"
+          SELECT
+            language,
+            count(*) AS count,
+            distinct_count(approx_count_distinct(visitor_id)) AS unique_count
+          FROM
+            metrics
+          WHERE
+            user_id = $1 AND ($2::text IS NULL OR (key = $2 AND domain IS NOT DISTINCT FROM $3))
+          GROUP BY
+            language
+          ORDER BY
+            count DESC
+          ",
+            &[&session.user.id, &params.key, &domain],
+        )
+        .await;
+
+    let languages = match query {
+        Ok(rows) => rows
+            .iter()
+            .map(|row| LanguageMetrics {
+                language: row.get("language"),
+                count: row.get("count"),
+                unique_count: row.get("unique_count"),
+            })
+            .collect(),
+        Err(err) => {
+            println!("{:?}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    (StatusCode::OK, Json(LanguageMetricsResponse { languages })).into_response()
+}
+
+async fn get_pixels(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    match links::find_pixels(connection, link.id) {
+        Ok(pixels) => (
+            StatusCode::OK,
+            Json(Pixels {
+                pixels,
+                delay_ms: link.pixel_delay_ms,
+            }),
+        )
+            .into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn set_pixels(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+    Json(mut payload): Json<Pixels>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let app_state = &mut *app_state;
 
-use axum::{
-    extract::{Query, State},
-    http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post},
-    Extension, Json, Router,
-};
-use rusqlite::Connection;
-use tokio::sync::Mutex;
+    if payload.delay_ms > MAX_PIXEL_DELAY_MS {
+        let error = ErrorResponse::new(
+            "invalid_delay",
+            format!("delay_ms must be at most {MAX_PIXEL_DELAY_MS}"),
+        );
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(error.with_field("delay_ms"))).into_response();
+    }
 
-use crate::{
-    entities::MetricsWithinInterval,
-    id::generate_id,
-    middleware::auth::UserSession,
-    sqlite,
-    structs::{CreateShortUrl, MetricsRequest, MetricsResponse, ShortUrlCreated},
-};
+    let hosts = match pixels::find_hosts(&mut app_state.connection) {
+        Ok(hosts) => hosts,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
 
-pub struct ApiAppState {
-    pg_conn: deadpool_postgres::Object,
-    connection: Connection,
+    for pixel in &mut payload.pixels {
+        if let Err(err) = check_destination(app_state, "pixels.url", &mut pixel.url) {
+            return err.into_response();
+        }
+
+        if !pixels::is_allowed(&hosts, &pixel.url) {
+            let error = ErrorResponse::new("pixel_not_allowed", "pixel host is not on the allowlist");
+            return (StatusCode::UNPROCESSABLE_ENTITY, Json(error.with_field("pixels.url"))).into_response();
+        }
+    }
+
+    let connection = &mut app_state.connection;
+
+    let link = match find_owned_link(connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    match api::set_pixels(connection, link.id, &payload.pixels, payload.delay_ms) {
+        Ok(()) => (StatusCode::OK, Json(payload)).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
 }
 
-pub fn router(pg_conn: deadpool_postgres::Object) -> Router {
-    let connection = sqlite::create_connection();
-    let state = Arc::new(Mutex::new(ApiAppState { connection, pg_conn }));
+async fn get_profile(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
 
-    Router::new()
-        .route("/create-short-url", post(create_short_url))
-        .route("/metrics", get(get_metrics))
-        .with_state(state)
+    match api::find_profile(&mut app_state.connection, session.user.id) {
+        Ok(profile) => (StatusCode::OK, Json(profile)).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
 }
 
-async fn create_short_url(
+async fn update_profile(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Json(payload): Json<UpdateProfile>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+
+    if let Some(display_name) = &payload.display_name
+        && display_name.trim().chars().count() > MAX_DISPLAY_NAME_LENGTH
+    {
+        let error = ErrorResponse::new(
+            "invalid_display_name",
+            format!("display_name must be at most {MAX_DISPLAY_NAME_LENGTH} characters long"),
+        );
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(error.with_field("display_name"))).into_response();
+    }
+
+    if api::update_profile(&mut app_state.connection, session.user.id, &payload).is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    match api::find_profile(&mut app_state.connection, session.user.id) {
+        Ok(profile) => (StatusCode::OK, Json(profile)).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+async fn sign_link(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(key): Path<String>,
+    Query(LinkDomain { domain }): Query<LinkDomain>,
+    headers: HeaderMap,
+    Json(payload): Json<SignLink>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+
+    let link = match find_owned_link(&mut app_state.connection, &session.user, domain.as_deref(), &key) {
+        Ok(link) => link,
+        Err(err) => return err.into_response(),
+    };
+
+    if payload.expires_at <= OffsetDateTime::now_utc() {
+        let error = ErrorResponse::new("invalid_expiry", "expires_at must be in the future");
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(error.with_field("expires_at"))).into_response();
+    }
+
+    // Normalized so the signature matches the address the visitor is seen with
+    let ip = match payload.ip.as_deref().map(str::parse::<IpAddr>) {
+        Some(Ok(ip)) => Some(ip.to_string()),
+        Some(Err(_)) => {
+            let error = ErrorResponse::new("invalid_ip", "ip is not a valid IP address");
+            return (StatusCode::UNPROCESSABLE_ENTITY, Json(error.with_field("ip"))).into_response();
+        }
+        None => None,
+    };
+
+    match app_state
+        .link_signer
+        .sign(&link.address(), payload.expires_at, ip.as_deref())
+    {
+        Some(query) => {
+            let Some(url) = short_url(&link, &headers, &format!("{key}?{query}")) else {
+                return StatusCode::BAD_REQUEST.into_response();
+            };
+            let signed = SignedLink {
+                url,
+                expires_at: payload.expires_at,
+            };
+            (StatusCode::CREATED, Json(signed)).into_response()
+        }
+        None => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorResponse::new(
+                "signing_disabled",
+                "no signing secret is configured",
+            )),
+        )
+            .into_response(),
+    }
+}
+
+async fn list_domains(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+
+    match domains::find_by_user(&mut app_state.connection, session.user.id) {
+        Ok(found) => {
+            let domains = found.into_iter().map(domain_response).collect();
+            (StatusCode::OK, Json(DomainsResponse { domains })).into_response()
+        }
+        Err(err) => {
+            println!("{:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Claims a hostname for the account. Links can be created on it once the TXT record from the response is found
+/// by the verification.
+async fn create_domain(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Json(payload): Json<CreateDomain>,
+) -> impl IntoResponse {
+    let Some(hostname) = domains::normalize_hostname(&payload.hostname) else {
+        let error = ErrorResponse::new("invalid_hostname", "hostname must be a domain name without port");
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(error.with_field("hostname"))).into_response();
+    };
+
+    let mut app_state = state.lock().await;
+
+    match domains::create(&mut app_state.connection, session.user.id, &hostname) {
+        Ok(domain) => (StatusCode::CREATED, Json(domain_response(domain))).into_response(),
+        Err(err) if api::is_unique_violation(&err) => {
+            let error = ErrorResponse::new("domain_taken", format!("domain '{hostname}' was already added"));
+            (StatusCode::CONFLICT, Json(error)).into_response()
+        }
+        Err(err) => {
+            println!("{:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn update_domain(
     State(state): State<Arc<Mutex<ApiAppState>>>,
     session: Extension<UserSession>,
-    Json(payload): Json<CreateShortUrl>,
+    Path(hostname): Path<String>,
+    Json(mut payload): Json<UpdateBrandedPages>,
 ) -> impl IntoResponse {
     let mut app_state = state.lock().await;
+    let app_state = &mut *app_state;
+
+    if let Err(err) = check_branded_pages(app_state, &mut payload) {
+        return err.into_response();
+    }
+
     let connection = &mut app_state.connection;
 
-    let mut retries = 0;
+    let domain = match find_owned_domain(connection, &session.user, &hostname) {
+        Ok(domain) => domain,
+        Err(err) => return err.into_response(),
+    };
 
-    while retries < 5 {
-        let id = generate_id();
-        match api::create_short_url(connection, session.user.id, &id, &payload.url) {
-            Ok(_) => return (StatusCode::CREATED, Json(ShortUrlCreated { id })).into_response(),
-            // Duplicate Key (code=1555)
-            Err(rusqlite::Error::SqliteFailure(err, _)) if err.extended_code == 1555 => retries += 1,
-            Err(_) => break,
+    match domains::update(connection, domain.id, &payload) {
+        Ok(domain) => (StatusCode::OK, Json(domain_response(domain))).into_response(),
+        Err(err) => {
+            println!("{:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
         }
     }
+}
 
-    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+/// Looks up the TXT record of the domain. The lock is released during the lookup, DNS can be slow.
+async fn verify_domain(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(hostname): Path<String>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+
+    let domain = match find_owned_domain(&mut app_state.connection, &session.user, &hostname) {
+        Ok(domain) => domain,
+        Err(err) => return err.into_response(),
+    };
+
+    if domain.verified_at.is_some() {
+        return (StatusCode::OK, Json(domain_response(domain))).into_response();
+    }
+
+    let resolver = app_state.resolver.clone();
+    drop(app_state);
+
+    let records = match resolver.lookup(&domains::verification_record(&domain.hostname)).await {
+        Ok(records) => records,
+        Err(err) => {
+            println!("{err}");
+            let error = ErrorResponse::new("dns_lookup_failed", "the DNS lookup failed, please try again later");
+            return (StatusCode::SERVICE_UNAVAILABLE, Json(error)).into_response();
+        }
+    };
+
+    if !domains::is_verified_by(&records, &domain.verification_token) {
+        let error = ErrorResponse::new(
+            "verification_failed",
+            format!(
+                "no TXT record '{}' found at '{}'",
+                domains::verification_value(&domain.verification_token),
+                domains::verification_record(&domain.hostname)
+            ),
+        );
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(error)).into_response();
+    }
+
+    let mut app_state = state.lock().await;
+
+    match domains::mark_verified(&mut app_state.connection, domain.id) {
+        Ok(domain) => (StatusCode::OK, Json(domain_response(domain))).into_response(),
+        Err(err) if api::is_unique_violation(&err) => {
+            let error = ErrorResponse::new(
+                "domain_taken",
+                format!("domain '{}' is already used by another account", domain.hostname),
+            );
+            (StatusCode::CONFLICT, Json(error)).into_response()
+        }
+        Err(err) => {
+            println!("{:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Domains with links can't be removed, not even deleted ones, whose keys stay reserved on the domain.
+async fn delete_domain(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Path(hostname): Path<String>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let connection = &mut app_state.connection;
+
+    let domain = match find_owned_domain(connection, &session.user, &hostname) {
+        Ok(domain) => domain,
+        Err(err) => return err.into_response(),
+    };
+
+    match domains::has_links(connection, domain.id) {
+        Ok(true) => {
+            let error = ErrorResponse::new("domain_in_use", "the domain has links");
+            return (StatusCode::CONFLICT, Json(error)).into_response();
+        }
+        Ok(false) => {}
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    match domains::delete(connection, domain.id) {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => {
+            println!("{:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Pages of the account, they apply to its links on every domain unless the domain has its own.
+async fn get_pages(State(state): State<Arc<Mutex<ApiAppState>>>, session: Extension<UserSession>) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+
+    match branding::find_account_pages(&mut app_state.connection, session.user.id) {
+        Ok(pages) => (StatusCode::OK, Json(pages)).into_response(),
+        Err(err) => {
+            println!("{:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn update_pages(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Json(mut payload): Json<UpdateBrandedPages>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let app_state = &mut *app_state;
+
+    if let Err(err) = check_branded_pages(app_state, &mut payload) {
+        return err.into_response();
+    }
+
+    match branding::update_account_pages(&mut app_state.connection, session.user.id, &payload) {
+        Ok(pages) => (StatusCode::OK, Json(pages)).into_response(),
+        Err(err) => {
+            println!("{:?}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Empty values clear a page, so they skip the checks.
+fn check_branded_pages(
+    app_state: &ApiAppState,
+    payload: &mut UpdateBrandedPages,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    if let Some(not_found_url) = payload.not_found_url.as_mut().filter(|url| !url.is_empty()) {
+        check_destination(app_state, "not_found_url", not_found_url)?;
+    }
+
+    if let Some(root_url) = payload.root_url.as_mut().filter(|url| !url.is_empty()) {
+        check_destination(app_state, "root_url", root_url)?;
+    }
+
+    let templates = [
+        ("not_found_template", &payload.not_found_template),
+        ("expired_template", &payload.expired_template),
+        ("blocked_template", &payload.blocked_template),
+        ("disabled_template", &payload.disabled_template),
+    ];
+
+    for (field, template) in templates {
+        if template
+            .as_ref()
+            .is_some_and(|template| template.len() > branding::MAX_TEMPLATE_LENGTH)
+        {
+            let error = ErrorResponse::new(
+                "template_too_long",
+                format!("templates must be at most {} bytes", branding::MAX_TEMPLATE_LENGTH),
+            );
+            return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(error.with_field(field))));
+        }
+    }
+
+    Ok(())
+}
+
+fn domain_response(domain: Domain) -> DomainResponse {
+    let verification = DomainVerification {
+        record_type: "TXT",
+        name: domains::verification_record(&domain.hostname),
+        value: domains::verification_value(&domain.verification_token),
+    };
+
+    DomainResponse { domain, verification }
+}
+
+fn find_owned_domain(
+    connection: &mut Connection,
+    user: &User,
+    hostname: &str,
+) -> Result<Domain, (StatusCode, Json<ErrorResponse>)> {
+    match domains::find_owned(connection, user.id, &hostname.to_ascii_lowercase()) {
+        Ok(Some(domain)) => Ok(domain),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(
+                "not_found",
+                format!("domain '{hostname}' does not exist"),
+            )),
+        )),
+        Err(_) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("internal_error", "domain lookup failed")),
+        )),
+    }
+}
+
+/// Absolute url of `path` on the link's domain. Custom domains are always served over https, the shared domain is the
+/// one the API is reached on.
+fn short_url(link: &Link, headers: &HeaderMap, path: &str) -> Option<String> {
+    match &link.domain {
+        Some(hostname) => Some(format!("https://{hostname}/{path}")),
+        None => {
+            let host = headers.string("host")?;
+            let scheme = headers
+                .string("cloudfront-forwarded-proto")
+                .unwrap_or_else(|| "https".to_owned());
+
+            Some(format!("{scheme}://{host}/{path}"))
+        }
+    }
+}
+
+/// Links on a custom domain are addressed by their key together with the domain's hostname.
+fn find_owned_link(
+    connection: &mut Connection,
+    user: &User,
+    domain: Option<&str>,
+    key: &str,
+) -> Result<Link, (StatusCode, Json<ErrorResponse>)> {
+    let domain_id = match domain {
+        Some(hostname) => match domains::find_owned(connection, user.id, &hostname.to_ascii_lowercase()) {
+            Ok(Some(domain)) => Some(domain.id),
+            Ok(None) => {
+                return Err((
+                    StatusCode::NOT_FOUND,
+                    Json(ErrorResponse::new(
+                        "not_found",
+                        format!("domain '{hostname}' does not exist"),
+                    )),
+                ))
+            }
+            Err(_) => {
+                return Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse::new("internal_error", "domain lookup failed")),
+                ))
+            }
+        },
+        None => None,
+    };
+
+    match links::find_link(connection, domain_id, key) {
+        Ok(Some(link)) if link.user_id == user.id => Ok(link),
+        Ok(Some(_)) => Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse::new("forbidden", "link belongs to another user")),
+        )),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new("not_found", format!("link '{key}' does not exist"))),
+        )),
+        Err(_) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse::new("internal_error", "link lookup failed")),
+        )),
+    }
 }
 
 async fn get_metrics(
@@ -63,11 +1643,12 @@ async fn get_metrics(
     session: Extension<UserSession>,
     Query(params): Query<MetricsRequest>,
 ) -> impl IntoResponse {
-    let app_state = state.lock().await;
+    let mut app_state = state.lock().await;
     let user_id = session.user.id;
 
     let minutes = params.measuring_interval_minutes;
     let interval = format!("{minutes} minutes");
+    let source = params.source.map(|source| source.as_str());
 
     let query = app_state
         .pg_conn
@@ -80,13 +1661,13 @@ async fn get_metrics(
           FROM
             metrics
           WHERE 
-            user_id = $2
+            user_id = $2 AND ($3::text IS NULL OR source = $3)
           GROUP BY 
             bucket
           ORDER BY
             bucket DESC
           ",
-            &[&interval, &user_id],
+            &[&interval, &user_id, &source],
         )
         .await;
 
@@ -105,8 +1686,167 @@ async fn get_metrics(
         }
     };
 
-    let response = MetricsResponse { metrics };
+    let permanent_keys = match api::find_permanent_redirects(&mut app_state.connection, user_id) {
+        Ok(redirects) => redirects,
+        Err(err) => {
+            println!("{:?}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let response = MetricsResponse {
+        metrics,
+        permanent_keys,
+    };
 
     // TODO: set cache-control headers
     (StatusCode::OK, Json(response)).into_response()
 }
+
+async fn list_conversion_goals(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+
+    match api::find_conversion_goals(&mut app_state.connection, session.user.id) {
+        Ok(goals) => (StatusCode::OK, Json(ConversionGoalsResponse { goals })).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// The returned token is used in the conversion pixel `/c/{token}.gif` on the destination's site.
+async fn create_conversion_goal(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Json(payload): Json<CreateConversionGoal>,
+) -> impl IntoResponse {
+    let name = payload.name.trim();
+
+    if name.is_empty() {
+        let error = ErrorResponse::new("invalid_name", "name must not be empty");
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(error.with_field("name"))).into_response();
+    }
+
+    let mut app_state = state.lock().await;
+
+    match api::create_conversion_goal(&mut app_state.connection, session.user.id, name) {
+        Ok(goal) => (StatusCode::CREATED, Json(goal)).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Each conversion is attributed to the visitor's last click on one of the user's links within the attribution
+/// window before it, conversions without such a click are not counted.
+async fn get_conversion_metrics(
+    State(state): State<Arc<Mutex<ApiAppState>>>,
+    session: Extension<UserSession>,
+    Query(params): Query<ConversionMetricsRequest>,
+) -> impl IntoResponse {
+    let app_state = state.lock().await;
+
+    let hours = params
+        .attribution_window_hours
+        .unwrap_or(DEFAULT_ATTRIBUTION_WINDOW_HOURS);
+
+    if hours == 0 || hours > MAX_ATTRIBUTION_WINDOW_HOURS {
+        let error = ErrorResponse::new(
+            "invalid_attribution_window",
+            format!("attribution_window_hours must be between 1 and {MAX_ATTRIBUTION_WINDOW_HOURS}"),
+        );
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(error.with_field("attribution_window_hours")),
+        )
+            .into_response();
+    }
+
+    let window = format!("{hours} hours");
+    let domain = params.domain.as_deref().map(str::to_ascii_lowercase);
+
+    let query = app_state
+        .pg_conn
+        .query(
+            r"
+          WITH attributed AS (
+            SELECT
+              last_click.key,
+              last_click.domain,
+              value
+            FROM
+              conversions
+              CROSS JOIN LATERAL (
+                SELECT key, domain FROM metrics
+                WHERE
+                  metrics.user_id = conversions.user_id
+                  AND metrics.visitor_id = conversions.visitor_id
+                  AND metrics.created_at <= conversions.created_at
+                  AND metrics.created_at > conversions.created_at - $2::text::interval
+                ORDER BY metrics.created_at DESC
+                LIMIT 1
+              ) AS last_click
+            WHERE
+              user_id = $1 AND ($4::text IS NULL OR goal = $4)
+          ),
+          conversion_counts AS (
+            SELECT key, domain, count(*) AS conversions, COALESCE(sum(value), 0) AS conversion_value
+            FROM attributed
+            GROUP BY key, domain
+          ),
+          click_counts AS (
+            SELECT key, domain, count(*) AS clicks
+            FROM metrics
+            WHERE user_id = $1 AND ($3::text IS NULL OR (key = $3 AND domain IS NOT DISTINCT FROM $5))
+            GROUP BY key, domain
+          )
+          SELECT
+            click_counts.key,
+            click_counts.domain,
+            clicks,
+            COALESCE(conversions, 0) AS conversions,
+            COALESCE(conversion_value, 0) AS conversion_value
+          FROM
+            click_counts
+            LEFT JOIN conversion_counts ON conversion_counts.key = click_counts.key
+              AND conversion_counts.domain IS NOT DISTINCT FROM click_counts.domain
+          ORDER BY
+            conversions DESC, click_counts.key, click_counts.domain
+          ",
+            &[&session.user.id, &window, &params.key, &params.goal, &domain],
+        )
+        .await;
+
+    let links = match query {
+        Ok(rows) => rows
+            .iter()
+            .map(|row| {
+                let clicks: i64 = row.get("clicks");
+                let conversions: i64 = row.get("conversions");
+
+                ConversionMetrics {
+                    key: row.get("key"),
+                    domain: row.get("domain"),
+                    clicks,
+                    conversions,
+                    conversion_rate: if clicks > 0 {
+                        conversions as f64 / clicks as f64
+                    } else {
+                        0.0
+                    },
+                    conversion_value: row.get("conversion_value"),
+                }
+            })
+            .collect(),
+        Err(err) => {
+            println!("{:?}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let response = ConversionMetricsResponse {
+        links,
+        attribution_window_hours: hours,
+    };
+
+    (StatusCode::OK, Json(response)).into_response()
+}
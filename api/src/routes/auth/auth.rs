@@ -1,5 +1,7 @@
+use std::fmt;
+
 use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    password_hash::{self, rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
 use rusqlite::{params, Connection};
@@ -8,42 +10,75 @@ use uuid::Uuid;
 
 use crate::entities::User;
 
-pub fn create_user(connection: &mut Connection, email: &str, password: &str) -> Result<User, rusqlite::Error> {
+/// Storing a record with a password can fail in the hasher as well as in the database.
+#[derive(Debug)]
+pub enum StoreError {
+    Hash(password_hash::Error),
+    Database(rusqlite::Error),
+}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(err: rusqlite::Error) -> Self {
+        StoreError::Database(err)
+    }
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Hash(err) => write!(f, "password can't be hashed: {err}"),
+            StoreError::Database(err) => write!(f, "database error: {err}"),
+        }
+    }
+}
+
+pub fn hash_password(password: &str) -> Result<String, StoreError> {
     let salt = SaltString::generate(&mut OsRng);
     let argon2 = Argon2::default();
 
-    let hash = match argon2.hash_password(password.as_bytes(), &salt) {
-        Ok(hash) => hash.to_string(),
-        _ => return Err(rusqlite::Error::QueryReturnedNoRows),
-    };
+    argon2
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(StoreError::Hash)
+}
 
-    connection.query_row(
-        "INSERT INTO users (email, pw_hash) VALUES (?1, ?2) RETURNING id, email",
+pub fn verify_hash(hash: &str, password: &str) -> bool {
+    let argon = Argon2::default();
+
+    PasswordHash::new(hash).is_ok_and(|parsed_hash| argon.verify_password(password.as_bytes(), &parsed_hash).is_ok())
+}
+
+pub fn create_user(connection: &mut Connection, email: &str, password: &str) -> Result<User, StoreError> {
+    let hash = hash_password(password)?;
+
+    let user = connection.query_row(
+        "INSERT INTO users (email, pw_hash) VALUES (?1, ?2) RETURNING id, email, is_admin",
         [email, &hash],
         |row| {
             let id: i64 = row.get("id")?;
             let email: String = row.get("email")?;
+            let is_admin: bool = row.get("is_admin")?;
 
-            Ok(User { id, email })
+            Ok(User { id, email, is_admin })
         },
-    )
+    )?;
+
+    Ok(user)
 }
 
 pub fn verify_password(connection: &mut Connection, email: &str, password: &str) -> Result<User, rusqlite::Error> {
     connection.query_row(
-        "SELECT pw_hash, id, email FROM users WHERE email = ?1",
+        "SELECT pw_hash, id, email, is_admin FROM users WHERE email = ?1",
         [email],
         |row| {
             let hash: String = row.get("pw_hash")?;
 
-            let argon = Argon2::default();
-            if let Ok(parsed_hash) = PasswordHash::new(&hash)
-                && argon.verify_password(password.as_bytes(), &parsed_hash).is_ok()
-            {
+            if verify_hash(&hash, password) {
                 let id: i64 = row.get("id")?;
                 let email: String = row.get("email")?;
+                let is_admin: bool = row.get("is_admin")?;
 
-                return Ok(User { email, id });
+                return Ok(User { email, id, is_admin });
             }
 
             Err(rusqlite::Error::QueryReturnedNoRows)
@@ -3,20 +3,25 @@ pub mod auth;
 use std::sync::Arc;
 
 use axum::{
+    extract::Query,
     extract::State,
     http::StatusCode,
-    response::{IntoResponse, Redirect},
+    response::{Html, IntoResponse, Redirect},
     routing::{get, post},
-    Json, Router,
+    Form, Json, Router,
+};
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    CookieJar,
 };
-use axum_extra::extract::{cookie::Cookie, CookieJar};
 use rusqlite::Connection;
+use time::OffsetDateTime;
 use tokio::sync::Mutex;
 
 use crate::{
     middleware::auth::SESSION_COOKIE,
-    sqlite,
-    structs::{Login, Signup},
+    pages, sqlite,
+    structs::{Login, LoginForm, LoginPage, Signup},
 };
 
 pub struct AuthAppState {
@@ -29,7 +34,8 @@ pub fn router() -> Router {
 
     Router::new()
         .route("/signup", post(signup))
-        .route("/login", post(login))
+        .route("/login", get(login_page).post(login))
+        .route("/login/form", post(login_form))
         .route("/logout", get(logout))
         .with_state(state)
 }
@@ -64,11 +70,67 @@ async fn login(
         Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     };
 
+    jar = jar.add(session_cookie(session_id, expires_at));
+
+    (jar, StatusCode::OK).into_response()
+}
+
+/// Browser login used by private links, sends the visitor back to `return_to` afterwards.
+async fn login_page(Query(query): Query<LoginPage>) -> impl IntoResponse {
+    Html(pages::login_form(return_path(query.return_to.as_deref()), None))
+}
+
+async fn login_form(
+    State(state): State<Arc<Mutex<AuthAppState>>>,
+    jar: CookieJar,
+    Form(form): Form<LoginForm>,
+) -> impl IntoResponse {
+    let mut app_state = state.lock().await;
+    let connection = &mut app_state.connection;
+    let return_to = return_path(form.return_to.as_deref());
+
+    let user = match auth::verify_password(connection, &form.email, &form.password) {
+        Ok(user) => user,
+        Err(_) => {
+            let page = pages::login_form(return_to, Some("Wrong email or password"));
+            return (StatusCode::UNAUTHORIZED, Html(page)).into_response();
+        }
+    };
+
+    let (session_id, expires_at) = match auth::create_session(connection, user.id) {
+        Ok(session) => session,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    (jar.add(session_cookie(session_id, expires_at)), Redirect::to(return_to)).into_response()
+}
+
+// Scoped to the whole site so the session also reaches the api and private short links, Lax still sends it along
+// when a private link is opened from another site
+fn session_cookie(session_id: String, expires_at: OffsetDateTime) -> Cookie<'static> {
     let mut cookie = Cookie::new(SESSION_COOKIE, session_id);
+    cookie.set_path("/");
+    cookie.set_http_only(true);
+    cookie.set_secure(true);
+    cookie.set_same_site(SameSite::Lax);
     cookie.set_expires(expires_at);
-    jar = jar.add(cookie);
+    cookie
+}
 
-    (jar, StatusCode::OK).into_response()
+/// Only local paths are accepted, anything else would turn the login into an open redirect. Browsers drop tabs and
+/// line breaks from urls, so whitespace and control characters are refused as well.
+fn return_path(return_to: Option<&str>) -> &str {
+    match return_to {
+        Some(path)
+            if path.starts_with('/')
+                && !path.starts_with("//")
+                && !path.starts_with("/\\")
+                && !path.chars().any(|c| c.is_whitespace() || c.is_control()) =>
+        {
+            path
+        }
+        _ => "/",
+    }
 }
 
 async fn logout(State(state): State<Arc<Mutex<AuthAppState>>>, jar: CookieJar) -> impl IntoResponse {
@@ -80,3 +142,27 @@ async fn logout(State(state): State<Arc<Mutex<AuthAppState>>>, jar: CookieJar) -
 
     (jar.remove(SESSION_COOKIE), Redirect::temporary("/")).into_response()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_local_paths_are_returned_to() {
+        assert_eq!(return_path(Some("/abc?utm_source=mail")), "/abc?utm_source=mail");
+
+        for path in [
+            "https://evil.example",
+            "//evil.example",
+            "/\\evil.example",
+            "/\t/evil.example",
+            "/\n/evil.example",
+            "/ /evil.example",
+            "evil.example",
+        ] {
+            assert_eq!(return_path(Some(path)), "/", "{path:?}");
+        }
+
+        assert_eq!(return_path(None), "/");
+    }
+}
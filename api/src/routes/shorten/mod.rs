@@ -0,0 +1,1124 @@
+pub mod visits;
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, PoisonError},
+    time::Duration,
+};
+
+use axum::{
+    extract::{ConnectInfo, OriginalUri, Path, Query, State},
+    http::{
+        header::{CACHE_CONTROL, CONTENT_SECURITY_POLICY, CONTENT_TYPE, LOCATION},
+        HeaderMap, HeaderValue, StatusCode, Uri,
+    },
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::get,
+    Form, Json, Router,
+};
+
+use axum_extra::extract::cookie::{Cookie, CookieJar, Key, SameSite, SignedCookieJar};
+use rusqlite::Connection;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use tokio::{sync::Mutex, time::interval};
+use url::form_urlencoded;
+
+use crate::{
+    blocklist::SharedBlocklist,
+    branding::{self, SiteCache},
+    device, domains,
+    entities::{ClickSource, Device, Domain, Link, PageKind, RedirectType, User, Visibility},
+    headers::TypedHeaderValues,
+    id::generate_id,
+    language, links,
+    metrics::{persist_conversion, persist_metrics, Conversion, Metric},
+    middleware::auth::{find_user_by_session_id, SESSION_COOKIE},
+    pages, passthrough,
+    rate_limit::RateLimiter,
+    routes::auth::auth::verify_hash,
+    signing::{self, LinkSigner, SignatureError},
+    sqlite,
+    structs::{
+        AppLinkComponent, AppLinkDetails, AppLinks, AppleAppSiteAssociation, AssetLink, AssetLinkTarget,
+        ConversionPixel, UnlockLink,
+    },
+    unfurl::{self, Metadata},
+};
+
+#[derive(Deserialize)]
+struct RedirectPath {
+    id: String,
+    rest: Option<String>,
+}
+
+const BUFFER_SIZE: usize = 1000;
+const UNLOCK_DURATION: time::Duration = time::Duration::hours(1);
+const UNLOCK_MAX_FAILURES: u32 = 5;
+const UNLOCK_FAILURE_WINDOW: Duration = Duration::from_secs(15 * 60);
+const VISITOR_COOKIE: &str = "visitor-id";
+const TRANSPARENT_GIF: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x02,
+    0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+// Persisted so returning visitors keep their A/B variant
+const VISITOR_COOKIE_MAX_AGE: time::Duration = time::Duration::days(365);
+const ARCHIVE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// Only the visitor's browser may keep a redirect, shared caches would skip the per-visitor handling
+const PERMANENT_CACHE_CONTROL: &str = "private, max-age=86400";
+const ARCHIVE_RETENTION: time::Duration = time::Duration::days(30);
+
+pub struct PublicAppState {
+    connection: Connection,
+    metrics_buffer: Vec<Metric>,
+    pg_pool: deadpool_postgres::Pool,
+    blocklist: SharedBlocklist,
+    cookie_key: Key,
+    unlock_attempts: RateLimiter,
+    link_signer: Arc<LinkSigner>,
+    http_client: reqwest::Client,
+    sites: SiteCache,
+}
+
+pub fn router(pg_pool: deadpool_postgres::Pool, blocklist: SharedBlocklist, link_signer: Arc<LinkSigner>) -> Router {
+    let connection = sqlite::create_connection();
+
+    let state = Arc::new(Mutex::new(PublicAppState {
+        connection,
+        metrics_buffer: Vec::with_capacity(BUFFER_SIZE),
+        pg_pool,
+        blocklist,
+        cookie_key: cookie_key(),
+        unlock_attempts: RateLimiter::new(UNLOCK_MAX_FAILURES, UNLOCK_FAILURE_WINDOW),
+        link_signer,
+        http_client: unfurl::client(),
+        sites: SiteCache::new(),
+    }));
+
+    let mut interval = interval(Duration::from_secs(10));
+    let app_state = state.clone();
+
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+            let mut app = app_state.lock().await;
+            let metrics: Vec<Metric> = app.metrics_buffer.drain(..).collect();
+
+            if let Ok(client) = app.pg_pool.get().await {
+                persist_metrics(client, metrics).await.unwrap();
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut connection = sqlite::create_connection();
+        let mut archive_interval = tokio::time::interval(ARCHIVE_INTERVAL);
+
+        loop {
+            archive_interval.tick().await;
+
+            match visits::archive_dead_links(&mut connection, ARCHIVE_RETENTION) {
+                Ok(0) => {}
+                Ok(archived) => println!("Archived {archived} dead links"),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+    });
+
+    Router::new()
+        .route("/", get(domain_root))
+        .route("/c/{pixel}", get(track_conversion))
+        .route("/q/{id}", get(scan_qr_code).post(unlock_link))
+        .route(
+            "/.well-known/apple-app-site-association",
+            get(apple_app_site_association),
+        )
+        .route("/apple-app-site-association", get(apple_app_site_association))
+        .route("/.well-known/assetlinks.json", get(asset_links))
+        .route("/{id}", get(redirect_to_url).post(unlock_link))
+        .route("/{id}/{*rest}", get(redirect_to_url).post(unlock_link))
+        .with_state(state)
+}
+
+async fn redirect_to_url(
+    headers: HeaderMap,
+    jar: CookieJar,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<Mutex<PublicAppState>>>,
+    Path(path): Path<RedirectPath>,
+    OriginalUri(uri): OriginalUri,
+) -> Result<Response, StatusCode> {
+    follow_link(headers, jar, addr, state, path, uri, ClickSource::Link).await
+}
+
+/// QR codes point here rather than at the link itself, so scans can be told apart from clicks.
+async fn scan_qr_code(
+    headers: HeaderMap,
+    jar: CookieJar,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<Mutex<PublicAppState>>>,
+    Path(path): Path<RedirectPath>,
+    OriginalUri(uri): OriginalUri,
+) -> Result<Response, StatusCode> {
+    follow_link(headers, jar, addr, state, path, uri, ClickSource::Qr).await
+}
+
+async fn follow_link(
+    headers: HeaderMap,
+    jar: CookieJar,
+    addr: SocketAddr,
+    state: Arc<Mutex<PublicAppState>>,
+    RedirectPath { id, rest }: RedirectPath,
+    uri: Uri,
+    source: ClickSource,
+) -> Result<Response, StatusCode> {
+    if rest.is_none()
+        && let Some(key) = id.strip_suffix('+')
+    {
+        return preview_link(&headers, &addr, &state, key, &uri).await;
+    }
+
+    let (visitor_id, is_new_visitor) = match jar.get(VISITOR_COOKIE) {
+        Some(cookie) => (cookie.value().to_owned(), false),
+        None => (generate_id(), true),
+    };
+
+    let mut app = state.lock().await;
+
+    let now = OffsetDateTime::now_utc();
+    let domain = find_request_domain(&mut app, &headers)?;
+
+    let link = match find_active_link(&mut app.connection, domain.as_deref(), &id, now) {
+        Err(StatusCode::NOT_FOUND) => {
+            let not_found = StatusCode::NOT_FOUND.into_response();
+            return branded_page(&mut app, domain.as_deref(), None, PageKind::NotFound, &id, not_found);
+        }
+        link => link?,
+    };
+
+    if link.is_expired(now) {
+        return expired_response(&mut app, domain.as_deref(), &link, now);
+    }
+
+    let access = match check_access(&mut app, &link, &headers, &addr, &uri, now) {
+        Ok(access) => access,
+        Err(denied) => return Ok(denied_response(denied, &uri)),
+    };
+
+    // Link previews of chat apps are not clicks
+    if unfurl::is_bot(&headers) {
+        if is_blocked(&app, &link.url) {
+            return blocked_page(&mut app, domain.as_deref(), &link, &link.url);
+        }
+
+        drop(app);
+        return unfurl_link(&state, &link, now).await;
+    }
+
+    let device = device::detect(&headers);
+
+    let target = match visits::select_target(&mut app.connection, &link, &headers, device, &visitor_id, now) {
+        Ok(target) => target,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    let target_url = target.url.as_deref().unwrap_or(&link.url);
+
+    let destination = match passthrough::destination(&link, target_url, access.query.as_deref(), rest.as_deref()) {
+        Some(destination) => destination,
+        None => return Err(StatusCode::NOT_FOUND),
+    };
+
+    if is_blocked(&app, &destination) {
+        return blocked_page(&mut app, domain.as_deref(), &link, &destination);
+    }
+
+    let pixels = match visits::find_allowed_pixels(&mut app.connection, link.id) {
+        Ok(pixels) => pixels,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    let handoff = match device {
+        Some(device) => match visits::find_handoff(&mut app.connection, link.id, device) {
+            Ok(handoff) => handoff,
+            Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+        },
+        None => None,
+    };
+
+    // A cached redirect must look the same for everyone, so it can't depend on the visit or set a cookie
+    let cacheable = link.redirect_type.is_permanent()
+        && link.is_static()
+        && !access.is_signed
+        && !is_new_visitor
+        && pixels.is_empty()
+        && handoff.is_none();
+
+    let cacheable = match cacheable {
+        true => match links::has_rules(&mut app.connection, link.id) {
+            Ok(has_rules) => !has_rules,
+            Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+        },
+        false => false,
+    };
+
+    if visits::count_click(&mut app.connection, &link).is_err() {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    let metric = Metric {
+        visitor_id: visitor_id.clone(),
+        shorthand_id: link.key.clone(),
+        domain: link.domain.clone(),
+        user_id: link.user_id,
+        created_at: now,
+        ip: headers
+            .string("cloudfront-viewer-address")
+            .unwrap_or_else(|| addr.ip().to_string()),
+        url: destination.clone(),
+        android: headers
+            .bool("cloudfront-is-android-viewer")
+            .or(device.map(|device| device == Device::Android)),
+        ios: headers
+            .bool("cloudfront-is-ios-viewer")
+            .or(device.map(|device| device == Device::Ios)),
+        mobile: headers.bool("cloudfront-is-mobile-viewer"),
+        region_name: headers.string("cloudfront-viewer-country-region-name"),
+        country: headers.string("cloudfront-viewer-country"),
+        city: headers.string("cloudfront-viewer-city"),
+        zip_code: headers.string("cloudfront-viewer-postal-code"),
+        time_zone: headers.string("cloudfront-viewer-time-zone"),
+        user_agent: headers.string("user-agent"),
+        longitude: headers.float("cloudfront-viewer-longitude"),
+        latitude: headers.float("cloudfront-viewer-latitude"),
+        revision_id: link.revision_id,
+        geo_rule_id: target.geo_rule_id,
+        variant_id: target.variant_id,
+        language: headers
+            .string("accept-language")
+            .and_then(|accept_language| language::preferences(&accept_language).into_iter().next()),
+        visitor_user_id: access.visitor_user_id,
+        rotation_id: target.rotation_id,
+        source,
+    };
+
+    app.metrics_buffer.push(metric);
+
+    if app.metrics_buffer.len() >= BUFFER_SIZE
+        && let Ok(client) = app.pg_pool.get().await
+    {
+        let metrics: Vec<Metric> = app.metrics_buffer.drain(..).collect();
+        tokio::spawn(persist_metrics(client, metrics));
+    }
+
+    // Pixels and the app handoff need a page to run in, so they replace the redirect
+    let mut response = if let Some((app_url, store_url)) = handoff {
+        let page = pages::app_handoff(&app_url, store_url.as_deref(), &destination);
+        ([(CACHE_CONTROL, "no-store")], Html(page)).into_response()
+    } else if pixels.is_empty() {
+        redirect(link.redirect_type, &destination, cacheable)
+    } else {
+        let page = pages::pixel_interstitial(&destination, &pixels, link.pixel_delay_ms);
+        (
+            [
+                (CONTENT_SECURITY_POLICY, pages::INTERSTITIAL_POLICY),
+                (CACHE_CONTROL, "no-store"),
+            ],
+            Html(page),
+        )
+            .into_response()
+    };
+
+    if link.is_restricted() || access.is_signed {
+        response
+            .headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_static("private, no-store"));
+    }
+
+    if cacheable {
+        return Ok(response);
+    }
+
+    // Refreshed whenever the response isn't cached, so older cookies pick up the cross-site attributes
+    let mut cookie = Cookie::new(VISITOR_COOKIE, visitor_id);
+    cookie.set_path("/");
+    cookie.set_max_age(VISITOR_COOKIE_MAX_AGE);
+    // The conversion pixel is loaded from the destination's site, so the cookie has to be sent cross-site
+    cookie.set_same_site(SameSite::None);
+    cookie.set_secure(true);
+
+    Ok((jar.add(cookie), response).into_response())
+}
+
+/// Custom domains can send visitors of their bare hostname somewhere, the shared domain has nothing to show.
+async fn domain_root(
+    headers: HeaderMap,
+    State(state): State<Arc<Mutex<PublicAppState>>>,
+) -> Result<Response, StatusCode> {
+    let mut app = state.lock().await;
+    let app = &mut *app;
+
+    let Some(domain) = find_request_domain(app, &headers)? else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    let pages = match app.sites.pages(&mut app.connection, Some(&domain), None) {
+        Ok(pages) => pages,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    match pages.root_url {
+        Some(root_url) => Ok(Redirect::temporary(&root_url).into_response()),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Lets the registered iOS apps open the deep links instead of Safari, paths are listed per key so regular links
+/// keep opening in the browser.
+async fn apple_app_site_association(
+    headers: HeaderMap,
+    State(state): State<Arc<Mutex<PublicAppState>>>,
+) -> Result<Response, StatusCode> {
+    let mut app = state.lock().await;
+    let domain = find_request_domain(&mut app, &headers)?;
+
+    let (apps, keys) = match (
+        links::find_mobile_apps(&mut app.connection),
+        links::find_deep_link_keys(&mut app.connection, domain.map(|domain| domain.id)),
+    ) {
+        (Ok(apps), Ok(keys)) => (apps, keys),
+        _ => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    let paths: Vec<String> = keys
+        .iter()
+        .flat_map(|key| [format!("/{key}"), format!("/{key}/*")])
+        .collect();
+
+    let details = apps
+        .into_iter()
+        .filter(|app| app.platform == Device::Ios)
+        .map(|app| AppLinkDetails {
+            app_ids: vec![app.app_id.clone()],
+            app_id: app.app_id,
+            paths: paths.clone(),
+            components: paths
+                .iter()
+                .map(|path| AppLinkComponent { path: path.clone() })
+                .collect(),
+        })
+        .collect();
+
+    let association = AppleAppSiteAssociation {
+        applinks: AppLinks {
+            apps: Vec::new(),
+            details,
+        },
+    };
+
+    Ok(([(CACHE_CONTROL, "public, max-age=3600")], Json(association)).into_response())
+}
+
+/// Digital Asset Links for the registered Android apps, Android verifies the whole domain rather than paths.
+async fn asset_links(State(state): State<Arc<Mutex<PublicAppState>>>) -> Result<Response, StatusCode> {
+    let mut app = state.lock().await;
+
+    let apps = match links::find_mobile_apps(&mut app.connection) {
+        Ok(apps) => apps,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    let asset_links: Vec<AssetLink> = apps
+        .into_iter()
+        .filter(|app| app.platform == Device::Android)
+        .map(|app| AssetLink {
+            relation: vec!["delegate_permission/common.handle_all_urls"],
+            target: AssetLinkTarget {
+                namespace: "android_app",
+                package_name: app.app_id,
+                sha256_cert_fingerprints: app.sha256_cert_fingerprints,
+            },
+        })
+        .collect();
+
+    Ok(([(CACHE_CONTROL, "public, max-age=3600")], Json(asset_links)).into_response())
+}
+
+/// Conversion pixel for the destination's site, `/c/{token}.gif` records a conversion for the goal with that
+/// token if the visitor came through one of our links before. It always answers with the image so pages don't break.
+async fn track_conversion(
+    jar: CookieJar,
+    State(state): State<Arc<Mutex<PublicAppState>>>,
+    Path(pixel): Path<String>,
+    Query(params): Query<ConversionPixel>,
+) -> Result<Response, StatusCode> {
+    let Some(token) = pixel.strip_suffix(".gif") else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    let pixel_response = (
+        [(CONTENT_TYPE, "image/gif"), (CACHE_CONTROL, "no-store")],
+        TRANSPARENT_GIF,
+    )
+        .into_response();
+
+    let Some(visitor_id) = jar.get(VISITOR_COOKIE).map(|cookie| cookie.value().to_owned()) else {
+        return Ok(pixel_response);
+    };
+
+    let mut app = state.lock().await;
+
+    let user_id = match visits::find_goal_owner(&mut app.connection, token) {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return Ok(pixel_response),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    let conversion = Conversion {
+        user_id,
+        goal: token.to_owned(),
+        visitor_id,
+        value: params.value.filter(|value| value.is_finite()),
+        created_at: OffsetDateTime::now_utc(),
+    };
+
+    if let Ok(client) = app.pg_pool.get().await {
+        tokio::spawn(persist_conversion(client, conversion));
+    }
+
+    Ok(pixel_response)
+}
+
+/// Per-link Open Graph values win over the ones read from the destination, which are cached for a while.
+async fn unfurl_link(state: &Mutex<PublicAppState>, link: &Link, now: OffsetDateTime) -> Result<Response, StatusCode> {
+    let custom = Metadata {
+        title: link.og_title.clone(),
+        description: link.og_description.clone(),
+        image: link.og_image.clone(),
+    };
+
+    let metadata = if custom.is_complete() {
+        custom
+    } else {
+        let mut app = state.lock().await;
+
+        let cached = match unfurl::find_cached(&mut app.connection, &link.url, now) {
+            Ok(cached) => cached,
+            Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+        };
+
+        let metadata = match cached {
+            Some(metadata) => metadata,
+            None => {
+                let client = app.http_client.clone();
+                drop(app);
+
+                let fetched = unfurl::fetch(&client, &link.url).await;
+
+                // Not storing it only means the next unfurl fetches again
+                let _ = unfurl::store(&mut state.lock().await.connection, &link.url, &fetched);
+                fetched
+            }
+        };
+
+        custom.or(metadata)
+    };
+
+    let page = pages::unfurl(&link.url, &metadata);
+
+    Ok(([(CACHE_CONTROL, "no-store")], Html(page)).into_response())
+}
+
+/// Shows where a link goes without redirecting or counting a click, reached by appending `+` to the key.
+async fn preview_link(
+    headers: &HeaderMap,
+    addr: &SocketAddr,
+    state: &Mutex<PublicAppState>,
+    key: &str,
+    uri: &Uri,
+) -> Result<Response, StatusCode> {
+    let mut app = state.lock().await;
+
+    let now = OffsetDateTime::now_utc();
+    let domain = find_request_domain(&mut app, headers)?;
+
+    let link = match find_active_link(&mut app.connection, domain.as_deref(), key, now) {
+        Err(StatusCode::NOT_FOUND) => {
+            let not_found = StatusCode::NOT_FOUND.into_response();
+            return branded_page(&mut app, domain.as_deref(), None, PageKind::NotFound, key, not_found);
+        }
+        link => link?,
+    };
+
+    let destination = match (link.is_expired(now), &link.fallback_url) {
+        (false, _) => &link.url,
+        (true, Some(fallback_url)) => fallback_url,
+        (true, None) => return expired_response(&mut app, domain.as_deref(), &link, now),
+    };
+
+    if let Err(denied) = check_access(&mut app, &link, headers, addr, uri, now) {
+        return Ok(denied_response(denied, uri));
+    }
+
+    let owner = match links::find_public_display_name(&mut app.connection, link.user_id) {
+        Ok(owner) => owner,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    // The clicks column is only maintained for links with a click limit, so the count comes from the metrics
+    let buffered = app
+        .metrics_buffer
+        .iter()
+        .filter(|metric| metric.shorthand_id == link.key && metric.domain == link.domain)
+        .count() as i64;
+    let pg_pool = app.pg_pool.clone();
+    drop(app);
+
+    let recorded: i64 = match pg_pool.get().await {
+        Ok(client) => match client
+            .query_one(
+                r"SELECT count(*) AS clicks FROM metrics
+                  WHERE user_id = $1 AND key = $2 AND domain IS NOT DISTINCT FROM $3",
+                &[&link.user_id, &link.key, &link.domain],
+            )
+            .await
+        {
+            Ok(row) => row.get("clicks"),
+            Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+        },
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    let page = pages::preview(
+        &link.key,
+        destination,
+        link.created_at,
+        owner.as_deref(),
+        recorded + buffered,
+    );
+
+    Ok(([(CACHE_CONTROL, "no-store")], Html(page)).into_response())
+}
+
+/// Looks up a link for visitors, links that are not active yet are treated as missing.
+fn find_active_link(
+    connection: &mut Connection,
+    domain: Option<&Domain>,
+    id: &str,
+    now: OffsetDateTime,
+) -> Result<Link, StatusCode> {
+    match links::find_link(connection, domain.map(|domain| domain.id), id) {
+        Ok(Some(link)) if !link.is_pending(now) => Ok(link),
+        Ok(_) => Err(StatusCode::NOT_FOUND),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// The verified custom domain the request was sent to, other hosts serve the links of the shared domain.
+fn find_request_domain(app: &mut PublicAppState, headers: &HeaderMap) -> Result<Option<Arc<Domain>>, StatusCode> {
+    let Some(hostname) = domains::request_hostname(headers) else {
+        return Ok(None);
+    };
+
+    app.sites
+        .domain(&mut app.connection, &hostname)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// The page the domain or account configured for `kind`, `default` when neither did. Unknown keys can also be sent
+/// to a not-found URL.
+fn branded_page(
+    app: &mut PublicAppState,
+    domain: Option<&Domain>,
+    user_id: Option<i64>,
+    kind: PageKind,
+    key: &str,
+    default: Response,
+) -> Result<Response, StatusCode> {
+    let pages = match app.sites.pages(&mut app.connection, domain, user_id) {
+        Ok(pages) => pages,
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    if let Some(template) = pages.template(kind) {
+        let status = match kind {
+            PageKind::NotFound => StatusCode::NOT_FOUND,
+            PageKind::Expired | PageKind::Disabled => StatusCode::GONE,
+            PageKind::Blocked => StatusCode::FORBIDDEN,
+        };
+        let page = branding::render(template, key, domain.map(|domain| domain.hostname.as_str()));
+
+        return Ok((
+            status,
+            [
+                (CONTENT_SECURITY_POLICY, branding::TEMPLATE_POLICY),
+                (CACHE_CONTROL, "no-store"),
+            ],
+            Html(page),
+        )
+            .into_response());
+    }
+
+    match pages.not_found_url.filter(|_| kind == PageKind::NotFound) {
+        Some(not_found_url) => Ok(Redirect::temporary(&not_found_url).into_response()),
+        None => Ok(default),
+    }
+}
+
+/// Where visitors of an expired link end up: its fallback, or the expired or disabled page.
+fn expired_response(
+    app: &mut PublicAppState,
+    domain: Option<&Domain>,
+    link: &Link,
+    now: OffsetDateTime,
+) -> Result<Response, StatusCode> {
+    let Some(fallback_url) = &link.fallback_url else {
+        let kind = if link.is_deactivated(now) {
+            PageKind::Disabled
+        } else {
+            PageKind::Expired
+        };
+        return branded_page(
+            app,
+            domain,
+            Some(link.user_id),
+            kind,
+            &link.key,
+            StatusCode::GONE.into_response(),
+        );
+    };
+
+    if is_blocked(app, fallback_url) {
+        return blocked_page(app, domain, link, fallback_url);
+    }
+
+    Ok(Redirect::temporary(fallback_url).into_response())
+}
+
+fn blocked_page(
+    app: &mut PublicAppState,
+    domain: Option<&Domain>,
+    link: &Link,
+    url: &str,
+) -> Result<Response, StatusCode> {
+    branded_page(
+        app,
+        domain,
+        Some(link.user_id),
+        PageKind::Blocked,
+        &link.key,
+        blocked(url),
+    )
+}
+
+struct Access {
+    is_signed: bool,
+    visitor_user_id: Option<i64>,
+    /// The incoming query without the signature parameters
+    query: Option<String>,
+}
+
+enum Denied {
+    Signature(SignatureError),
+    Login,
+    NotOwner,
+    Password,
+}
+
+/// Runs the signature, session and password checks.
+fn check_access(
+    app: &mut PublicAppState,
+    link: &Link,
+    headers: &HeaderMap,
+    addr: &SocketAddr,
+    uri: &Uri,
+    now: OffsetDateTime,
+) -> Result<Access, Denied> {
+    let (is_signed, query) = check_signature(&app.link_signer, link, uri, &client_ip(headers, addr), now)?;
+
+    let visitor_user_id = match link.visibility {
+        Visibility::Private if !is_signed => Some(check_owner(&mut app.connection, link, headers)?),
+        _ => None,
+    };
+
+    if link.password_hash.is_some() && !is_signed && !is_unlocked(headers, &app.cookie_key, link, now) {
+        return Err(Denied::Password);
+    }
+
+    Ok(Access {
+        is_signed,
+        visitor_user_id,
+        query,
+    })
+}
+
+/// A valid signature grants access on its own, that's what signed urls are shared for. Returns whether the url was
+/// signed and the query without the signature parameters.
+fn check_signature(
+    link_signer: &LinkSigner,
+    link: &Link,
+    uri: &Uri,
+    ip: &str,
+    now: OffsetDateTime,
+) -> Result<(bool, Option<String>), Denied> {
+    let (signed, query) = signing::split_query(uri.query());
+
+    match signed {
+        Some(signed) => match link_signer.verify(&link.address(), &signed, ip, now) {
+            Ok(()) => Ok((true, query)),
+            Err(err) => Err(Denied::Signature(err)),
+        },
+        None if link.require_signature => Err(Denied::Signature(SignatureError::Missing)),
+        None => Ok((false, query)),
+    }
+}
+
+/// Only the owner's session opens a private link, anyone can sign up so being logged in isn't enough.
+fn check_owner(connection: &mut Connection, link: &Link, headers: &HeaderMap) -> Result<i64, Denied> {
+    match session_user(connection, headers) {
+        Some(user) if user.id == link.user_id => Ok(user.id),
+        Some(_) => Err(Denied::NotOwner),
+        None => Err(Denied::Login),
+    }
+}
+
+fn denied_response(denied: Denied, uri: &Uri) -> Response {
+    match denied {
+        Denied::Signature(SignatureError::Missing) => (
+            StatusCode::FORBIDDEN,
+            "This link can only be opened through a signed url",
+        )
+            .into_response(),
+        Denied::Signature(SignatureError::Invalid) => (StatusCode::FORBIDDEN, "Invalid signature").into_response(),
+        Denied::Signature(SignatureError::Expired) => (StatusCode::GONE, "This link has expired").into_response(),
+        Denied::Login => login_redirect(uri),
+        Denied::NotOwner => (
+            StatusCode::FORBIDDEN,
+            [(CACHE_CONTROL, "no-store")],
+            "This link is private",
+        )
+            .into_response(),
+        Denied::Password => password_form(StatusCode::OK, None),
+    }
+}
+
+async fn unlock_link(
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<Mutex<PublicAppState>>>,
+    Path(RedirectPath { id, .. }): Path<RedirectPath>,
+    OriginalUri(uri): OriginalUri,
+    Form(form): Form<UnlockLink>,
+) -> Result<Response, StatusCode> {
+    let mut app = state.lock().await;
+
+    // The password form is also shown on preview pages
+    let id = id.strip_suffix('+').unwrap_or(&id);
+
+    let domain = find_request_domain(&mut app, &headers)?;
+
+    let link = match links::find_link(&mut app.connection, domain.map(|domain| domain.id), id) {
+        Ok(Some(link)) => link,
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    };
+
+    let Some(password_hash) = &link.password_hash else {
+        return Ok(Redirect::to(&uri.to_string()).into_response());
+    };
+
+    let attempt_id = format!("{}:{}", client_ip(&headers, &addr), link.id);
+
+    if app.unlock_attempts.is_limited(&attempt_id) {
+        let error = "Too many failed attempts, please try again later";
+        return Ok(password_form(StatusCode::TOO_MANY_REQUESTS, Some(error)));
+    }
+
+    if !verify_hash(password_hash, &form.password) {
+        app.unlock_attempts.record_failure(&attempt_id);
+        return Ok(password_form(StatusCode::UNAUTHORIZED, Some("Wrong password")));
+    }
+
+    app.unlock_attempts.reset(&attempt_id);
+
+    let cookie = unlock_cookie(&link, password_hash, OffsetDateTime::now_utc());
+    let jar = SignedCookieJar::from_headers(&headers, app.cookie_key.clone()).add(cookie);
+
+    Ok((jar, Redirect::to(&uri.to_string())).into_response())
+}
+
+fn session_user(connection: &mut Connection, headers: &HeaderMap) -> Option<User> {
+    let jar = CookieJar::from_headers(headers);
+    let session_id = jar.get(SESSION_COOKIE)?.value();
+
+    find_user_by_session_id(connection, session_id).ok()
+}
+
+fn login_redirect(uri: &Uri) -> Response {
+    let return_to: String = form_urlencoded::byte_serialize(uri.to_string().as_bytes()).collect();
+    let location = format!("/auth/login?return_to={return_to}");
+
+    (
+        StatusCode::SEE_OTHER,
+        [(LOCATION, location.as_str()), (CACHE_CONTROL, "no-store")],
+    )
+        .into_response()
+}
+
+/// Cookies are kept per host, so the key is enough to tell links apart.
+fn unlock_cookie_name(key: &str) -> String {
+    format!("unlock-{key}")
+}
+
+/// Unlock cookies are signed and carry their own expiry, so they can't be forged or extended by the visitor.
+fn unlock_cookie(link: &Link, password_hash: &str, now: OffsetDateTime) -> Cookie<'static> {
+    let expires_at = (now + UNLOCK_DURATION).unix_timestamp();
+    let value = format!("{}:{expires_at}", unlock_grant(link.id, password_hash));
+
+    let mut cookie = Cookie::new(unlock_cookie_name(&link.key), value);
+    cookie.set_path("/");
+    cookie.set_http_only(true);
+    cookie.set_max_age(UNLOCK_DURATION);
+    cookie
+}
+
+/// Ties an unlock to the link and the password it was given for, so it neither opens a link that took over the key
+/// nor survives a password change.
+fn unlock_grant(link_id: i64, password_hash: &str) -> String {
+    let fingerprint: String = Sha256::digest(password_hash.as_bytes())[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+
+    format!("{link_id}:{fingerprint}")
+}
+
+fn is_unlocked(headers: &HeaderMap, cookie_key: &Key, link: &Link, now: OffsetDateTime) -> bool {
+    let Some(password_hash) = &link.password_hash else {
+        return true;
+    };
+
+    SignedCookieJar::from_headers(headers, cookie_key.clone())
+        .get(&unlock_cookie_name(&link.key))
+        .and_then(|cookie| {
+            let (grant, expires_at) = cookie.value().rsplit_once(':')?;
+
+            if grant != unlock_grant(link.id, password_hash) {
+                return None;
+            }
+
+            expires_at.parse::<i64>().ok()
+        })
+        .is_some_and(|expires_at| now.unix_timestamp() < expires_at)
+}
+
+fn password_form(status: StatusCode, error: Option<&str>) -> Response {
+    (status, [(CACHE_CONTROL, "no-store")], Html(pages::password_form(error))).into_response()
+}
+
+/// Reads `COOKIE_SECRET` (at least 64 bytes), otherwise a random key is used and unlocked links have to be
+/// unlocked again after a restart.
+fn cookie_key() -> Key {
+    match std::env::var("COOKIE_SECRET").map(|secret| Key::try_from(secret.as_bytes())) {
+        Ok(Ok(key)) => key,
+        Ok(Err(_)) => {
+            println!("COOKIE_SECRET must be at least 64 bytes long, using a random key");
+            Key::generate()
+        }
+        Err(_) => Key::generate(),
+    }
+}
+
+/// The visitor's IP without the port CloudFront appends to the viewer address.
+fn client_ip(headers: &HeaderMap, addr: &SocketAddr) -> String {
+    match headers.string("cloudfront-viewer-address") {
+        Some(address) => match address.rsplit_once(':') {
+            Some((ip, _)) => ip.to_owned(),
+            None => address,
+        },
+        None => addr.ip().to_string(),
+    }
+}
+
+/// Links can be blocked after they were created, so destinations are checked on every visit.
+fn is_blocked(app: &PublicAppState, url: &str) -> bool {
+    app.blocklist
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .find_match(url)
+        .is_some()
+}
+
+fn blocked(url: &str) -> Response {
+    (StatusCode::FORBIDDEN, Html(pages::blocked(url))).into_response()
+}
+
+fn redirect(redirect_type: RedirectType, url: &str, cacheable: bool) -> Response {
+    let status = match redirect_type {
+        RedirectType::MovedPermanently => StatusCode::MOVED_PERMANENTLY,
+        RedirectType::Found => StatusCode::FOUND,
+        RedirectType::TemporaryRedirect => StatusCode::TEMPORARY_REDIRECT,
+        RedirectType::PermanentRedirect => StatusCode::PERMANENT_REDIRECT,
+        RedirectType::MetaRefresh => {
+            return ([(CACHE_CONTROL, "no-store")], Html(pages::meta_refresh(url))).into_response();
+        }
+    };
+
+    // Browsers keep permanent redirects even without a max-age, so anything that varies per visit is marked as
+    // uncacheable. Temporary ones must hit us on every click to be counted.
+    let cache_control = if redirect_type.is_permanent() && cacheable {
+        PERMANENT_CACHE_CONTROL
+    } else {
+        "no-store"
+    };
+
+    (status, [(LOCATION, url), (CACHE_CONTROL, cache_control)]).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::header::{COOKIE, SET_COOKIE};
+    use serde_json::json;
+
+    use super::*;
+    use crate::routes::{api::api, auth::auth};
+
+    fn create_private_link(connection: &mut Connection, user_id: i64) -> Link {
+        let payload = serde_json::from_value(json!({ "url": "https://example.com", "visibility": "private" })).unwrap();
+        api::create_short_url(connection, user_id, "private", None, &payload).unwrap();
+
+        links::find_link(connection, None, "private").unwrap().unwrap()
+    }
+
+    fn session_headers(connection: &mut Connection, email: &str) -> (i64, HeaderMap) {
+        let user = auth::create_user(connection, email, "password").unwrap();
+        let (session_id, _) = auth::create_session(connection, user.id).unwrap();
+
+        let mut headers = HeaderMap::new();
+        let cookie = format!("{SESSION_COOKIE}={session_id}");
+        headers.insert(COOKIE, HeaderValue::from_str(&cookie).unwrap());
+
+        (user.id, headers)
+    }
+
+    fn create_signed_link(connection: &mut Connection, require_signature: bool) -> Link {
+        let payload = serde_json::from_value(json!({
+            "url": "https://example.com",
+            "require_signature": require_signature,
+        }))
+        .unwrap();
+        api::create_short_url(connection, 1, "signed", None, &payload).unwrap();
+
+        links::find_link(connection, None, "signed").unwrap().unwrap()
+    }
+
+    #[test]
+    fn unsigned_visits_are_refused_when_a_signature_is_required() {
+        let mut connection = sqlite::create_test_connection();
+        let signer = LinkSigner::from_secrets("current:secret");
+        let link = create_signed_link(&mut connection, true);
+        let now = OffsetDateTime::now_utc();
+
+        let unsigned: Uri = "/signed?utm_source=mail".parse().unwrap();
+        assert!(matches!(
+            check_signature(&signer, &link, &unsigned, "203.0.113.7", now),
+            Err(Denied::Signature(SignatureError::Missing))
+        ));
+
+        let query = signer
+            .sign(&link.address(), now + time::Duration::hours(1), None)
+            .unwrap();
+        let signed: Uri = format!("/signed?utm_source=mail&{query}").parse().unwrap();
+        assert!(matches!(
+            check_signature(&signer, &link, &signed, "203.0.113.7", now),
+            Ok((true, Some(query))) if query == "utm_source=mail"
+        ));
+    }
+
+    #[test]
+    fn signatures_stay_optional_unless_required() {
+        let mut connection = sqlite::create_test_connection();
+        let signer = LinkSigner::from_secrets("current:secret");
+        let link = create_signed_link(&mut connection, false);
+        let uri: Uri = "/signed".parse().unwrap();
+
+        assert!(matches!(
+            check_signature(&signer, &link, &uri, "203.0.113.7", OffsetDateTime::now_utc()),
+            Ok((false, None))
+        ));
+    }
+
+    #[test]
+    fn private_links_open_for_their_owner() {
+        let mut connection = sqlite::create_test_connection();
+        let (owner_id, headers) = session_headers(&mut connection, "owner@example.com");
+        let link = create_private_link(&mut connection, owner_id);
+
+        assert!(matches!(check_owner(&mut connection, &link, &headers), Ok(id) if id == owner_id));
+    }
+
+    #[test]
+    fn private_links_are_denied_to_other_users() {
+        let mut connection = sqlite::create_test_connection();
+        let (owner_id, _) = session_headers(&mut connection, "owner@example.com");
+        let (_, headers) = session_headers(&mut connection, "other@example.com");
+        let link = create_private_link(&mut connection, owner_id);
+
+        assert!(matches!(
+            check_owner(&mut connection, &link, &headers),
+            Err(Denied::NotOwner)
+        ));
+    }
+
+    #[test]
+    fn private_links_ask_visitors_without_a_session_to_log_in() {
+        let mut connection = sqlite::create_test_connection();
+        let (owner_id, _) = session_headers(&mut connection, "owner@example.com");
+        let link = create_private_link(&mut connection, owner_id);
+
+        assert!(matches!(
+            check_owner(&mut connection, &link, &HeaderMap::new()),
+            Err(Denied::Login)
+        ));
+    }
+
+    fn unlocked_headers(cookie_key: &Key, cookie: Cookie<'static>) -> HeaderMap {
+        let response = SignedCookieJar::new(cookie_key.clone()).add(cookie).into_response();
+        let set_cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+        let pair = set_cookie.split(';').next().unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_str(pair).unwrap());
+        headers
+    }
+
+    #[test]
+    fn unlocks_only_hold_for_the_password_they_were_given_for() {
+        let mut connection = sqlite::create_test_connection();
+        let cookie_key = Key::generate();
+        let now = OffsetDateTime::now_utc();
+
+        let payload = serde_json::from_value(json!({ "url": "https://example.com", "password": "first" })).unwrap();
+        api::create_short_url(&mut connection, 1, "locked", None, &payload).unwrap();
+        let link = links::find_link(&mut connection, None, "locked").unwrap().unwrap();
+
+        let cookie = unlock_cookie(&link, link.password_hash.as_deref().unwrap(), now);
+        let headers = unlocked_headers(&cookie_key, cookie);
+        assert!(is_unlocked(&headers, &cookie_key, &link, now));
+        assert!(!is_unlocked(&headers, &cookie_key, &link, now + UNLOCK_DURATION));
+        assert!(!is_unlocked(&headers, &Key::generate(), &link, now));
+
+        let payload = serde_json::from_value(json!({ "password": "second" })).unwrap();
+        api::update_link(&mut connection, &link, 1, &payload).unwrap();
+        let changed = links::find_link(&mut connection, None, "locked").unwrap().unwrap();
+        assert!(!is_unlocked(&headers, &cookie_key, &changed, now));
+
+        let other = Link {
+            id: link.id + 1,
+            ..link
+        };
+        assert!(!is_unlocked(&headers, &cookie_key, &other, now));
+    }
+}
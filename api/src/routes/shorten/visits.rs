@@ -0,0 +1,500 @@
+use axum::http::HeaderMap;
+use rusqlite::{Connection, OptionalExtension};
+use time::OffsetDateTime;
+
+use crate::{
+    entities::{Device, Link, Pixel, RotationDestination, RotationMode},
+    geo,
+    headers::TypedHeaderValues,
+    language, links, pixels, schedule, variants,
+};
+
+/// The destination chosen for a single visit and the rule that chose it, `url` is `None` if the link's
+/// default destination applies.
+#[derive(Default)]
+pub struct Target {
+    pub url: Option<String>,
+    pub geo_rule_id: Option<i64>,
+    pub variant_id: Option<i64>,
+    pub rotation_id: Option<i64>,
+}
+
+/// Picks the destination for a visit. An active schedule window overrides everything else, followed by
+/// device rules, geo rules, language rules, rotating destinations and A/B variants. Rule tables are only queried until one of them matches.
+pub fn select_target(
+    connection: &mut Connection,
+    link: &Link,
+    headers: &HeaderMap,
+    device: Option<Device>,
+    visitor_id: &str,
+    now: OffsetDateTime,
+) -> Result<Target, rusqlite::Error> {
+    let windows = links::find_schedule(connection, link.id)?;
+    let time_zone = headers.string("cloudfront-viewer-time-zone");
+
+    if let Some(window) = schedule::find_active(&windows, now, time_zone.as_deref()) {
+        return Ok(Target {
+            url: Some(window.url.clone()),
+            ..Default::default()
+        });
+    }
+
+    if let Some(device) = device
+        && let Some(url) = links::find_device_url(connection, link.id, device)?
+    {
+        return Ok(Target {
+            url: Some(url),
+            ..Default::default()
+        });
+    }
+
+    let geo_rules = links::find_geo_rules(connection, link.id)?;
+
+    if let Some(rule) = geo::find_match(&geo_rules, headers) {
+        return Ok(Target {
+            url: Some(rule.url.clone()),
+            geo_rule_id: Some(rule.id),
+            ..Default::default()
+        });
+    }
+
+    if let Some(accept_language) = headers.string("accept-language") {
+        let language_rules = links::find_language_rules(connection, link.id)?;
+
+        if let Some(rule) = language::negotiate(&language_rules, &accept_language) {
+            return Ok(Target {
+                url: Some(rule.url.clone()),
+                ..Default::default()
+            });
+        }
+    }
+
+    if let Some(mode) = link.rotation
+        && let Some(destination) = rotate(connection, link.id, mode)?
+    {
+        return Ok(Target {
+            url: Some(destination.url),
+            rotation_id: Some(destination.id),
+            ..Default::default()
+        });
+    }
+
+    let variants = links::find_variants(connection, link.id)?;
+
+    if let Some(variant) = variants::pick(&variants, &link.key, visitor_id) {
+        return Ok(Target {
+            url: Some(variant.url.clone()),
+            variant_id: Some(variant.id),
+            ..Default::default()
+        });
+    }
+
+    Ok(Target::default())
+}
+
+/// The link's pixels whose host is still on the allowlist, hosts removed by an admin stop loading right away.
+pub fn find_allowed_pixels(connection: &mut Connection, link_id: i64) -> Result<Vec<Pixel>, rusqlite::Error> {
+    let pixels = links::find_pixels(connection, link_id)?;
+
+    if pixels.is_empty() {
+        return Ok(pixels);
+    }
+
+    let hosts = pixels::find_hosts(connection)?;
+
+    Ok(pixels
+        .into_iter()
+        .filter(|pixel| pixels::is_allowed(&hosts, &pixel.url))
+        .collect())
+}
+
+/// App URL and store fallback for a deep link opened on `device`. The link's own store URL wins over the one of
+/// the first registered app, without either the visitor falls through to the regular destination.
+pub fn find_handoff(
+    connection: &mut Connection,
+    link_id: i64,
+    device: Device,
+) -> Result<Option<(String, Option<String>)>, rusqlite::Error> {
+    let Some(deep_link) = links::find_deep_link(connection, link_id)? else {
+        return Ok(None);
+    };
+
+    let store_url = match device {
+        Device::Ios => deep_link.ios_store_url,
+        Device::Android => deep_link.android_store_url,
+    };
+
+    let store_url = match store_url {
+        Some(store_url) => Some(store_url),
+        None => links::find_mobile_apps(connection)?
+            .into_iter()
+            .filter(|app| app.platform == device)
+            .find_map(|app| app.store_url),
+    };
+
+    Ok(Some((deep_link.app_url, store_url)))
+}
+
+/// Moves the rotation of the link on by one click and returns the destination to serve. The link's cursor is bumped
+/// in the same transaction, so concurrent clicks never get the same slot and the position survives restarts.
+fn rotate(
+    connection: &mut Connection,
+    link_id: i64,
+    mode: RotationMode,
+) -> Result<Option<RotationDestination>, rusqlite::Error> {
+    let transaction = connection.transaction()?;
+
+    let cursor: i64 = transaction
+        .prepare_cached(
+            "UPDATE urls SET rotation_cursor = rotation_cursor + 1 WHERE id = ?1 RETURNING rotation_cursor",
+        )?
+        .query_row([link_id], |row| row.get("rotation_cursor"))?;
+
+    let destinations = links::find_rotation(&transaction, link_id)?;
+
+    if destinations.is_empty() {
+        return Ok(None);
+    }
+
+    let destination = match mode {
+        RotationMode::RoundRobin => {
+            let index = (cursor - 1).rem_euclid(destinations.len() as i64) as usize;
+            destinations.into_iter().nth(index)
+        }
+        // The cursor doubles as a logical clock, destinations that were never served come first
+        RotationMode::LeastRecentlyServed => transaction
+            .prepare_cached(
+                "SELECT id, url, hits FROM url_rotations WHERE url_id = ?1 ORDER BY last_served NULLS FIRST, position LIMIT 1",
+            )?
+            .query_row([link_id], |row| {
+                Ok(RotationDestination {
+                    id: row.get("id")?,
+                    url: row.get("url")?,
+                    hits: row.get("hits")?,
+                })
+            })
+            .optional()?,
+    };
+
+    if let Some(destination) = &destination {
+        transaction
+            .prepare_cached("UPDATE url_rotations SET hits = hits + 1, last_served = ?2 WHERE id = ?1")?
+            .execute((destination.id, cursor))?;
+    }
+
+    transaction.commit()?;
+
+    Ok(destination)
+}
+
+/// The user that owns the conversion goal with `token`.
+pub fn find_goal_owner(connection: &mut Connection, token: &str) -> Result<Option<i64>, rusqlite::Error> {
+    connection
+        .prepare_cached("SELECT user_id FROM conversion_goals WHERE token = ?1")?
+        .query_row([token], |row| row.get("user_id"))
+        .optional()
+}
+
+/// Only links with a click limit are counted, so unlimited links stay read-only on the hot path.
+pub fn count_click(connection: &mut Connection, link: &Link) -> Result<usize, rusqlite::Error> {
+    if link.max_clicks.is_none() {
+        return Ok(0);
+    }
+
+    let mut update = connection.prepare_cached(
+        r"UPDATE urls SET
+            clicks = clicks + 1,
+            exhausted_at = IIF(clicks + 1 >= max_clicks, unixepoch(), NULL)
+          WHERE id = ?1",
+    )?;
+
+    update.execute([link.id])
+}
+
+/// Moves links that have been expired or exhausted for longer than `retention` into `urls_archive`. Their key can
+/// be taken again afterwards, their revisions are handed over to the archived row and everything else stored for the
+/// link is deleted along with it by the foreign keys.
+pub fn archive_dead_links(connection: &mut Connection, retention: time::Duration) -> Result<usize, rusqlite::Error> {
+    let cutoff = (OffsetDateTime::now_utc() - retention).unix_timestamp();
+    let transaction = connection.transaction()?;
+
+    transaction.execute(
+        r"INSERT INTO urls_archive (
+            url_id, key, domain_id, url, user_id, created_at, expires_at, max_clicks, clicks, archived_at
+          )
+          SELECT id, key, domain_id, url, user_id, created_at, expires_at, max_clicks, clicks, unixepoch() FROM urls
+          WHERE expires_at <= ?1 OR exhausted_at <= ?1",
+        [cutoff],
+    )?;
+
+    transaction.execute(
+        r"UPDATE url_revisions
+          SET archive_id = (SELECT id FROM urls_archive WHERE urls_archive.url_id = url_revisions.url_id)
+          WHERE url_id IN (SELECT id FROM urls WHERE expires_at <= ?1 OR exhausted_at <= ?1)",
+        [cutoff],
+    )?;
+
+    let archived = transaction.execute(
+        "DELETE FROM urls WHERE expires_at <= ?1 OR exhausted_at <= ?1",
+        [cutoff],
+    )?;
+    transaction.commit()?;
+
+    Ok(archived)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use axum::http::HeaderValue;
+
+    use super::*;
+    use crate::{
+        entities::{DeviceRule, GeoRule, LanguageRule, ScheduleWindow, UrlVariant},
+        routes::api::api,
+        sqlite,
+    };
+
+    fn create_link(connection: &mut Connection, key: &str, payload: serde_json::Value) -> Link {
+        let payload = serde_json::from_value(payload).unwrap();
+        api::create_short_url(connection, 1, key, None, &payload).unwrap();
+
+        links::find_link(connection, None, key).unwrap().unwrap()
+    }
+
+    #[test]
+    fn links_expire_at_their_expiry_date() {
+        let mut connection = sqlite::create_test_connection();
+        let now = OffsetDateTime::now_utc();
+        let expires_at = (now + time::Duration::hours(1)).unix_timestamp() * 1000;
+
+        let link = create_link(
+            &mut connection,
+            "expiring",
+            json!({ "url": "https://example.com", "expires_at": expires_at }),
+        );
+
+        assert!(!link.is_expired(now));
+        assert!(link.is_expired(now + time::Duration::hours(1)));
+    }
+
+    #[test]
+    fn links_expire_once_the_click_limit_is_reached() {
+        let mut connection = sqlite::create_test_connection();
+        let now = OffsetDateTime::now_utc();
+        let mut link = create_link(
+            &mut connection,
+            "limited",
+            json!({ "url": "https://example.com", "max_clicks": 2 }),
+        );
+
+        for _ in 0..2 {
+            assert!(!link.is_expired(now));
+            count_click(&mut connection, &link).unwrap();
+            link = links::find_link(&mut connection, None, "limited").unwrap().unwrap();
+        }
+
+        assert_eq!(link.clicks, 2);
+        assert!(link.is_expired(now));
+    }
+
+    #[test]
+    fn expiry_click_limit_and_fallback_can_be_removed() {
+        let mut connection = sqlite::create_test_connection();
+        let now = OffsetDateTime::now_utc();
+        let expired_at = (now - time::Duration::hours(1)).unix_timestamp() * 1000;
+        let link = create_link(
+            &mut connection,
+            "renewed",
+            json!({
+                "url": "https://example.com",
+                "expires_at": expired_at,
+                "max_clicks": 1,
+                "fallback_url": "https://example.com/gone",
+            }),
+        );
+        count_click(&mut connection, &link).unwrap();
+
+        let payload = serde_json::from_value(json!({ "expires_at": 0, "max_clicks": 0, "fallback_url": "" })).unwrap();
+        api::update_link(&mut connection, &link, 1, &payload).unwrap();
+
+        let link = links::find_link(&mut connection, None, "renewed").unwrap().unwrap();
+        assert_eq!(link.expires_at, None);
+        assert_eq!(link.max_clicks, None);
+        assert_eq!(link.fallback_url, None);
+        assert!(!link.is_expired(now));
+    }
+
+    #[test]
+    fn activation_window_can_be_removed() {
+        let mut connection = sqlite::create_test_connection();
+        let now = OffsetDateTime::now_utc();
+        let link = create_link(
+            &mut connection,
+            "window",
+            json!({
+                "url": "https://example.com",
+                "active_from": (now + time::Duration::days(1)).unix_timestamp() * 1000,
+                "active_until": (now + time::Duration::days(2)).unix_timestamp() * 1000,
+            }),
+        );
+        assert!(link.active_from.is_some() && link.active_until.is_some());
+
+        let payload = serde_json::from_value(json!({ "active_from": 0, "active_until": 0 })).unwrap();
+        api::update_link(&mut connection, &link, 1, &payload).unwrap();
+
+        let link = links::find_link(&mut connection, None, "window").unwrap().unwrap();
+        assert_eq!(link.active_from, None);
+        assert_eq!(link.active_until, None);
+    }
+
+    #[test]
+    fn clicks_are_not_counted_without_a_limit() {
+        let mut connection = sqlite::create_test_connection();
+        let link = create_link(&mut connection, "unlimited", json!({ "url": "https://example.com" }));
+
+        count_click(&mut connection, &link).unwrap();
+
+        let link = links::find_link(&mut connection, None, "unlimited").unwrap().unwrap();
+        assert_eq!(link.clicks, 0);
+        assert!(!link.is_expired(OffsetDateTime::now_utc()));
+    }
+
+    #[test]
+    fn archived_keys_are_reused_without_the_old_rules() {
+        let mut connection = sqlite::create_test_connection();
+        let expired_at = (OffsetDateTime::now_utc() - time::Duration::days(1)).unix_timestamp() * 1000;
+        let old = create_link(
+            &mut connection,
+            "reused",
+            json!({ "url": "https://old.example.com", "expires_at": expired_at }),
+        );
+        let rules = [DeviceRule {
+            device: Device::Ios,
+            url: "https://old.example.com/ios".to_owned(),
+        }];
+        api::set_device_rules(&mut connection, old.id, &rules).unwrap();
+
+        assert_eq!(archive_dead_links(&mut connection, time::Duration::ZERO).unwrap(), 1);
+
+        let link = create_link(&mut connection, "reused", json!({ "url": "https://new.example.com" }));
+        assert_ne!(link.id, old.id);
+        assert!(links::find_device_rules(&mut connection, link.id).unwrap().is_empty());
+        assert_eq!(api::find_revisions(&mut connection, link.id).unwrap().len(), 1);
+
+        let archived_revisions: i64 = connection
+            .query_row(
+                r"SELECT count(*) FROM url_revisions
+                    JOIN urls_archive ON urls_archive.id = url_revisions.archive_id
+                  WHERE urls_archive.url_id = ?1 AND url_revisions.url = 'https://old.example.com'",
+                [old.id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(archived_revisions, 1);
+    }
+
+    fn url(target: &Target) -> Option<&str> {
+        target.url.as_deref()
+    }
+
+    #[test]
+    fn rules_are_applied_in_priority_order() {
+        let mut connection = sqlite::create_test_connection();
+        let now = OffsetDateTime::now_utc();
+        let link_id = create_link(&mut connection, "rules", json!({ "url": "https://example.com" })).id;
+
+        let mut headers = HeaderMap::new();
+        headers.insert("cloudfront-viewer-country", HeaderValue::from_static("DE"));
+        headers.insert("accept-language", HeaderValue::from_static("fr-FR,fr;q=0.9"));
+
+        let window = ScheduleWindow {
+            id: 0,
+            starts_at: Some(now - time::Duration::hours(1)),
+            ends_at: Some(now + time::Duration::hours(1)),
+            local_time: false,
+            url: "https://example.com/launch".to_owned(),
+        };
+        let device_rule = DeviceRule {
+            device: Device::Ios,
+            url: "https://example.com/ios".to_owned(),
+        };
+        let geo_rules = [
+            GeoRule {
+                id: 0,
+                continent: None,
+                country: Some("US".to_owned()),
+                region: None,
+                url: "https://example.com/us".to_owned(),
+            },
+            GeoRule {
+                id: 0,
+                continent: Some("EU".to_owned()),
+                country: None,
+                region: None,
+                url: "https://example.com/eu".to_owned(),
+            },
+        ];
+        let language_rule = LanguageRule {
+            language: "fr".to_owned(),
+            url: "https://example.com/fr".to_owned(),
+        };
+        let rotation = ["https://a.example.com", "https://b.example.com"].map(|url| RotationDestination {
+            id: 0,
+            url: url.to_owned(),
+            hits: 0,
+        });
+        let variant = UrlVariant {
+            id: 0,
+            name: Some("b".to_owned()),
+            url: "https://example.com/b".to_owned(),
+            weight: 1,
+        };
+
+        api::set_schedule(&mut connection, link_id, &[window]).unwrap();
+        api::set_device_rules(&mut connection, link_id, &[device_rule]).unwrap();
+        api::set_geo_rules(&mut connection, link_id, &geo_rules).unwrap();
+        api::set_language_rules(&mut connection, link_id, &[language_rule]).unwrap();
+        api::set_rotation(&mut connection, link_id, Some(RotationMode::RoundRobin), &rotation).unwrap();
+        api::set_variants(&mut connection, link_id, &[variant]).unwrap();
+        let link = links::find_link(&mut connection, None, "rules").unwrap().unwrap();
+
+        let select = |connection: &mut Connection, link: &Link, device| {
+            select_target(connection, link, &headers, device, "visitor", now).unwrap()
+        };
+
+        let target = select(&mut connection, &link, Some(Device::Ios));
+        assert_eq!(url(&target), Some("https://example.com/launch"));
+
+        api::set_schedule(&mut connection, link_id, &[]).unwrap();
+        let target = select(&mut connection, &link, Some(Device::Ios));
+        assert_eq!(url(&target), Some("https://example.com/ios"));
+
+        // Rules for other devices and countries are skipped
+        let target = select(&mut connection, &link, Some(Device::Android));
+        assert_eq!(url(&target), Some("https://example.com/eu"));
+        assert!(target.geo_rule_id.is_some());
+
+        api::set_geo_rules(&mut connection, link_id, &[]).unwrap();
+        let target = select(&mut connection, &link, None);
+        assert_eq!(url(&target), Some("https://example.com/fr"));
+
+        api::set_language_rules(&mut connection, link_id, &[]).unwrap();
+        let first = select(&mut connection, &link, None);
+        let second = select(&mut connection, &link, None);
+        assert_eq!(url(&first), Some("https://a.example.com"));
+        assert_eq!(url(&second), Some("https://b.example.com"));
+        assert!(first.rotation_id.is_some());
+
+        api::set_rotation(&mut connection, link_id, None, &[]).unwrap();
+        let link = links::find_link(&mut connection, None, "rules").unwrap().unwrap();
+        let target = select(&mut connection, &link, None);
+        assert_eq!(url(&target), Some("https://example.com/b"));
+        assert!(target.variant_id.is_some());
+
+        api::set_variants(&mut connection, link_id, &[]).unwrap();
+        let target = select(&mut connection, &link, None);
+        assert_eq!(url(&target), None);
+    }
+}
@@ -0,0 +1,24 @@
+use time::{OffsetDateTime, PrimitiveDateTime};
+use time_tz::{timezones, OffsetDateTimeExt};
+
+use crate::entities::ScheduleWindow;
+
+/// Returns the first window that contains `now`. Windows in local time hold wall-clock times stored as UTC
+/// and are compared against the wall-clock time in the visitor's time zone, or UTC if it's unknown.
+pub fn find_active<'a>(
+    windows: &'a [ScheduleWindow],
+    now: OffsetDateTime,
+    time_zone: Option<&str>,
+) -> Option<&'a ScheduleWindow> {
+    let local_now = time_zone
+        .and_then(timezones::get_by_name)
+        .map(|time_zone| now.to_timezone(time_zone))
+        .map(|local| PrimitiveDateTime::new(local.date(), local.time()).assume_utc())
+        .unwrap_or(now);
+
+    windows.iter().find(|window| {
+        let now = if window.local_time { local_now } else { now };
+
+        window.starts_at.is_none_or(|starts_at| starts_at <= now) && window.ends_at.is_none_or(|ends_at| now < ends_at)
+    })
+}
@@ -0,0 +1,266 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use time::OffsetDateTime;
+use url::form_urlencoded;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const EXPIRES_PARAM: &str = "exp";
+const KEY_ID_PARAM: &str = "kid";
+const SIGNATURE_PARAM: &str = "sig";
+const BOUND_PARAM: &str = "bound";
+
+struct SigningKey {
+    id: String,
+    secret: Vec<u8>,
+}
+
+/// Signs self-expiring variants of short links, nothing is stored per signed url.
+///
+/// Secrets are read from `LINK_SIGNING_SECRETS` as a comma separated list of `id:secret` pairs. The first one signs
+/// new urls, all of them are accepted when verifying so a secret can be rotated out without breaking shared urls.
+pub struct LinkSigner {
+    keys: Vec<SigningKey>,
+}
+
+#[derive(Debug)]
+pub enum SignatureError {
+    Missing,
+    Invalid,
+    Expired,
+}
+
+/// The signature parameters of an incoming request, missing values fail the verification.
+pub struct SignedQuery {
+    expires_at: Option<i64>,
+    key_id: Option<String>,
+    signature: Option<String>,
+    bound: bool,
+}
+
+impl LinkSigner {
+    pub fn from_env() -> Self {
+        LinkSigner::from_secrets(&std::env::var("LINK_SIGNING_SECRETS").unwrap_or_default())
+    }
+
+    pub fn from_secrets(secrets: &str) -> Self {
+        let keys = secrets
+            .split(',')
+            .filter_map(|entry| entry.trim().split_once(':'))
+            .filter(|(id, secret)| !id.is_empty() && !secret.is_empty())
+            .map(|(id, secret)| SigningKey {
+                id: id.to_owned(),
+                secret: secret.as_bytes().to_vec(),
+            })
+            .collect();
+
+        LinkSigner { keys }
+    }
+
+    /// Returns the query string for a signed url of `key`, `ip` binds it to a single visitor.
+    pub fn sign(&self, key: &str, expires_at: OffsetDateTime, ip: Option<&str>) -> Option<String> {
+        let signing_key = self.keys.first()?;
+        let expires_at = expires_at.unix_timestamp();
+        let signature = to_hex(
+            &mac(signing_key, key, expires_at, ip.unwrap_or_default())
+                .finalize()
+                .into_bytes(),
+        );
+
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        query
+            .append_pair(EXPIRES_PARAM, &expires_at.to_string())
+            .append_pair(KEY_ID_PARAM, &signing_key.id);
+
+        if ip.is_some() {
+            query.append_pair(BOUND_PARAM, "1");
+        }
+
+        Some(query.append_pair(SIGNATURE_PARAM, &signature).finish())
+    }
+
+    /// The signature is checked before the expiry so a forged url never reports itself as expired.
+    pub fn verify(&self, key: &str, signed: &SignedQuery, ip: &str, now: OffsetDateTime) -> Result<(), SignatureError> {
+        let (Some(expires_at), Some(key_id), Some(signature)) = (signed.expires_at, &signed.key_id, &signed.signature)
+        else {
+            return Err(SignatureError::Invalid);
+        };
+
+        let signing_key = self
+            .keys
+            .iter()
+            .find(|signing_key| signing_key.id == *key_id)
+            .ok_or(SignatureError::Invalid)?;
+        let signature = from_hex(signature).ok_or(SignatureError::Invalid)?;
+        let ip = if signed.bound { ip } else { "" };
+
+        mac(signing_key, key, expires_at, ip)
+            .verify_slice(&signature)
+            .map_err(|_| SignatureError::Invalid)?;
+
+        if expires_at <= now.unix_timestamp() {
+            return Err(SignatureError::Expired);
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits the signature parameters off the query, the remaining parameters are forwarded as usual.
+pub fn split_query(query: Option<&str>) -> (Option<SignedQuery>, Option<String>) {
+    let Some(query) = query else {
+        return (None, None);
+    };
+
+    if !form_urlencoded::parse(query.as_bytes()).any(|(name, _)| name == SIGNATURE_PARAM) {
+        return (None, Some(query.to_owned()));
+    }
+
+    let mut signed = SignedQuery {
+        expires_at: None,
+        key_id: None,
+        signature: None,
+        bound: false,
+    };
+    let mut rest = form_urlencoded::Serializer::new(String::new());
+
+    for (name, value) in form_urlencoded::parse(query.as_bytes()) {
+        match &*name {
+            EXPIRES_PARAM => signed.expires_at = value.parse().ok(),
+            KEY_ID_PARAM => signed.key_id = Some(value.into_owned()),
+            SIGNATURE_PARAM => signed.signature = Some(value.into_owned()),
+            BOUND_PARAM => signed.bound = true,
+            _ => {
+                rest.append_pair(&name, &value);
+            }
+        }
+    }
+
+    let rest = rest.finish();
+
+    (Some(signed), (!rest.is_empty()).then_some(rest))
+}
+
+fn mac(signing_key: &SigningKey, key: &str, expires_at: i64, ip: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(&signing_key.secret).expect("HMAC accepts keys of any length");
+    mac.update(format!("{key}\n{expires_at}\n{ip}").as_bytes());
+    mac
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) || !value.is_ascii() {
+        return None;
+    }
+
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&value[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Duration;
+
+    use super::*;
+
+    const IP: &str = "203.0.113.7";
+
+    fn verify_query(signer: &LinkSigner, key: &str, query: &str, ip: &str) -> Result<(), SignatureError> {
+        let (signed, _) = split_query(Some(query));
+
+        signer.verify(key, &signed.unwrap(), ip, OffsetDateTime::now_utc())
+    }
+
+    #[test]
+    fn signed_urls_verify_until_they_expire() {
+        let signer = LinkSigner::from_secrets("current:secret");
+        let now = OffsetDateTime::now_utc();
+        let query = signer.sign("abc", now + Duration::hours(1), None).unwrap();
+        let (signed, _) = split_query(Some(&query));
+        let signed = signed.unwrap();
+
+        assert!(signer.verify("abc", &signed, IP, now).is_ok());
+        assert!(matches!(
+            signer.verify("abc", &signed, IP, now + Duration::hours(1)),
+            Err(SignatureError::Expired)
+        ));
+    }
+
+    #[test]
+    fn signatures_only_fit_their_link_and_secret() {
+        let signer = LinkSigner::from_secrets("current:secret");
+        let query = signer
+            .sign("abc", OffsetDateTime::now_utc() + Duration::hours(1), None)
+            .unwrap();
+
+        assert!(matches!(
+            verify_query(&signer, "abd", &query, IP),
+            Err(SignatureError::Invalid)
+        ));
+
+        let other = LinkSigner::from_secrets("current:other-secret");
+        assert!(matches!(
+            verify_query(&other, "abc", &query, IP),
+            Err(SignatureError::Invalid)
+        ));
+    }
+
+    #[test]
+    fn tampered_expiry_is_rejected_as_invalid() {
+        let signer = LinkSigner::from_secrets("current:secret");
+        let expires_at = OffsetDateTime::now_utc() - Duration::minutes(1);
+        let query = signer.sign("abc", expires_at, None).unwrap();
+        let extended = query.replace(
+            &format!("exp={}", expires_at.unix_timestamp()),
+            &format!("exp={}", (expires_at + Duration::days(1)).unix_timestamp()),
+        );
+
+        assert!(matches!(
+            verify_query(&signer, "abc", &extended, IP),
+            Err(SignatureError::Invalid)
+        ));
+    }
+
+    #[test]
+    fn bound_urls_only_work_for_their_ip() {
+        let signer = LinkSigner::from_secrets("current:secret");
+        let query = signer
+            .sign("abc", OffsetDateTime::now_utc() + Duration::hours(1), Some(IP))
+            .unwrap();
+
+        assert!(verify_query(&signer, "abc", &query, IP).is_ok());
+        assert!(matches!(
+            verify_query(&signer, "abc", &query, "198.51.100.1"),
+            Err(SignatureError::Invalid)
+        ));
+    }
+
+    #[test]
+    fn rotated_secrets_keep_verifying() {
+        let old = LinkSigner::from_secrets("old:first");
+        let query = old
+            .sign("abc", OffsetDateTime::now_utc() + Duration::hours(1), None)
+            .unwrap();
+        let rotated = LinkSigner::from_secrets("new:second,old:first");
+
+        assert!(verify_query(&rotated, "abc", &query, IP).is_ok());
+        assert!(matches!(
+            verify_query(&LinkSigner::from_secrets("new:second"), "abc", &query, IP),
+            Err(SignatureError::Invalid)
+        ));
+    }
+
+    #[test]
+    fn signature_parameters_are_split_off_the_query() {
+        let (signed, rest) = split_query(Some("utm_source=mail&exp=1&kid=a&sig=00"));
+
+        assert!(signed.is_some());
+        assert_eq!(rest.as_deref(), Some("utm_source=mail"));
+        assert!(split_query(Some("utm_source=mail")).0.is_none());
+    }
+}
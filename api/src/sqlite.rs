@@ -7,8 +7,11 @@ mod sqlite_migrations {
     embed_migrations!("./migrations/sqlite");
 }
 
+/// Foreign keys are only enforced once the migrations ran, tables that are rebuilt would break them halfway through.
 pub fn run_migrations(conn: &mut rusqlite::Connection) {
+    conn.pragma_update(None, "foreign_keys", false).unwrap();
     sqlite_migrations::migrations::runner().run(conn).unwrap();
+    conn.pragma_update(None, "foreign_keys", true).unwrap();
 }
 
 pub fn create_connection() -> Connection {
@@ -19,6 +22,16 @@ pub fn create_connection() -> Connection {
     connection.pragma_update(None, "journal_mode", "WAL").unwrap();
     connection.pragma_update(None, "synchronous", "NORMAL").unwrap();
     connection.pragma_update(None, "wal_checkpoint", "TRUNCATE").unwrap();
+    connection.pragma_update(None, "foreign_keys", true).unwrap();
+
+    connection
+}
+
+/// A fresh in-memory database with all migrations applied.
+#[cfg(test)]
+pub fn create_test_connection() -> Connection {
+    let mut connection = Connection::open_in_memory().unwrap();
+    run_migrations(&mut connection);
 
     connection
 }
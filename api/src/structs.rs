@@ -1,15 +1,179 @@
 use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
 
-use crate::entities::MetricsWithinInterval;
+use crate::entities::{
+    ClickSource, ConversionGoal, ConversionMetrics, DeviceRule, Domain, GeoRule, LanguageMetrics, LanguageRule, Link,
+    MetricsWithinInterval, MobileApp, Pixel, QueryConflict, RedirectType, RotationDestination, RotationMode,
+    ScheduleWindow, UrlRevision, UrlVariant, VariantMetrics, Visibility,
+};
 
 #[derive(Deserialize)]
 pub struct CreateShortUrl {
     pub url: String,
+    pub alias: Option<String>,
+    #[serde(default, with = "time::serde::timestamp::milliseconds::option")]
+    pub expires_at: Option<OffsetDateTime>,
+    pub max_clicks: Option<u32>,
+    pub fallback_url: Option<String>,
+    pub redirect_type: Option<RedirectType>,
+    pub forward_query: Option<bool>,
+    pub query_conflict: Option<QueryConflict>,
+    pub forward_path: Option<bool>,
+    #[serde(default, with = "time::serde::timestamp::milliseconds::option")]
+    pub active_from: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::timestamp::milliseconds::option")]
+    pub active_until: Option<OffsetDateTime>,
+    pub password: Option<String>,
+    pub visibility: Option<Visibility>,
+    pub require_signature: Option<bool>,
+    pub og_title: Option<String>,
+    pub og_description: Option<String>,
+    pub og_image: Option<String>,
+    /// Hostname of a verified custom domain, the link is created on the shared domain without it.
+    pub domain: Option<String>,
 }
 
 #[derive(Serialize)]
 pub struct ShortUrlCreated {
     pub id: String,
+    pub domain: Option<String>,
+}
+
+/// Links on a custom domain are addressed by its hostname next to their key, without it the shared domain is meant.
+#[derive(Deserialize)]
+pub struct LinkDomain {
+    pub domain: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateShortUrl {
+    pub url: Option<String>,
+    #[serde(default, with = "time::serde::timestamp::milliseconds::option")]
+    pub expires_at: Option<OffsetDateTime>,
+    pub max_clicks: Option<u32>,
+    pub fallback_url: Option<String>,
+    pub redirect_type: Option<RedirectType>,
+    pub forward_query: Option<bool>,
+    pub query_conflict: Option<QueryConflict>,
+    pub forward_path: Option<bool>,
+    #[serde(default, with = "time::serde::timestamp::milliseconds::option")]
+    pub active_from: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::timestamp::milliseconds::option")]
+    pub active_until: Option<OffsetDateTime>,
+    pub password: Option<String>,
+    pub visibility: Option<Visibility>,
+    pub require_signature: Option<bool>,
+    pub og_title: Option<String>,
+    pub og_description: Option<String>,
+    pub og_image: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkSort {
+    #[default]
+    CreatedAt,
+    Key,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+#[derive(Deserialize)]
+pub struct LinksRequest {
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+    #[serde(default)]
+    pub sort: LinkSort,
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+#[derive(Serialize)]
+pub struct LinksResponse {
+    pub links: Vec<Link>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct HistoryResponse {
+    pub revisions: Vec<UrlRevision>,
+}
+
+#[derive(Deserialize)]
+pub struct Rollback {
+    pub revision_id: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeviceRules {
+    pub rules: Vec<DeviceRule>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GeoRules {
+    pub rules: Vec<GeoRule>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LanguageRules {
+    pub rules: Vec<LanguageRule>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Rotation {
+    pub mode: Option<RotationMode>,
+    pub destinations: Vec<RotationDestination>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UrlVariants {
+    pub variants: Vec<UrlVariant>,
+}
+
+#[derive(Serialize)]
+pub struct VariantMetricsResponse {
+    pub variants: Vec<VariantMetrics>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Schedule {
+    pub windows: Vec<ScheduleWindow>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Pixels {
+    pub pixels: Vec<Pixel>,
+    #[serde(default = "default_pixel_delay_ms")]
+    pub delay_ms: u32,
+}
+
+fn default_pixel_delay_ms() -> u32 {
+    500
+}
+
+#[derive(Deserialize)]
+pub struct SignLink {
+    #[serde(with = "time::serde::timestamp::milliseconds")]
+    pub expires_at: OffsetDateTime,
+    pub ip: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SignedLink {
+    pub url: String,
+    #[serde(with = "time::serde::timestamp::milliseconds")]
+    pub expires_at: OffsetDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct UnlockLink {
+    pub password: String,
 }
 
 #[derive(Deserialize)]
@@ -24,12 +188,255 @@ pub struct Login {
     pub password: String,
 }
 
+#[derive(Serialize)]
+pub struct Profile {
+    pub display_name: Option<String>,
+    pub public_profile: bool,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateProfile {
+    pub display_name: Option<String>,
+    pub public_profile: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateDomain {
+    pub hostname: String,
+}
+
+/// Used for the pages of accounts and of domains alike.
+#[derive(Deserialize)]
+pub struct UpdateBrandedPages {
+    pub not_found_url: Option<String>,
+    pub not_found_template: Option<String>,
+    pub root_url: Option<String>,
+    pub expired_template: Option<String>,
+    pub blocked_template: Option<String>,
+    pub disabled_template: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DomainResponse {
+    #[serde(flatten)]
+    pub domain: Domain,
+    pub verification: DomainVerification,
+}
+
+/// The DNS record the owner has to create before the domain can be used.
+#[derive(Serialize)]
+pub struct DomainVerification {
+    pub record_type: &'static str,
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Serialize)]
+pub struct DomainsResponse {
+    pub domains: Vec<DomainResponse>,
+}
+
+#[derive(Deserialize)]
+pub struct LoginPage {
+    pub return_to: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    pub email: String,
+    pub password: String,
+    pub return_to: Option<String>,
+}
+
 #[derive(Deserialize)]
 pub struct MetricsRequest {
     pub measuring_interval_minutes: u8,
+    /// Only counts visits of this source, `qr` for scans and `link` for regular clicks.
+    pub source: Option<ClickSource>,
+}
+
+#[derive(Deserialize)]
+pub struct ConversionMetricsRequest {
+    pub key: Option<String>,
+    pub domain: Option<String>,
+    pub goal: Option<String>,
+    pub attribution_window_hours: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct ConversionMetricsResponse {
+    pub links: Vec<ConversionMetrics>,
+    pub attribution_window_hours: u32,
+}
+
+#[derive(Deserialize)]
+pub struct CreateConversionGoal {
+    pub name: String,
+}
+
+#[derive(Serialize)]
+pub struct ConversionGoalsResponse {
+    pub goals: Vec<ConversionGoal>,
+}
+
+#[derive(Deserialize)]
+pub struct ConversionPixel {
+    pub value: Option<f64>,
+}
+
+#[derive(Deserialize)]
+pub struct LanguageMetricsRequest {
+    pub key: Option<String>,
+    pub domain: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct LanguageMetricsResponse {
+    pub languages: Vec<LanguageMetrics>,
 }
 
 #[derive(Serialize)]
 pub struct MetricsResponse {
     pub metrics: Vec<MetricsWithinInterval>,
+    /// Links with a permanent redirect, browsers cache those so repeat visits are undercounted.
+    pub permanent_keys: Vec<PermanentRedirect>,
+}
+
+#[derive(Serialize)]
+pub struct PermanentRedirect {
+    pub key: String,
+    pub domain: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct BlocklistEntry {
+    pub entry: String,
+}
+
+#[derive(Serialize)]
+pub struct BlocklistResponse {
+    pub entries: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MobileApps {
+    pub apps: Vec<MobileApp>,
+}
+
+/// `/.well-known/apple-app-site-association`, with both the current and the pre iOS 13 keys.
+#[derive(Serialize)]
+pub struct AppleAppSiteAssociation {
+    pub applinks: AppLinks,
+}
+
+#[derive(Serialize)]
+pub struct AppLinks {
+    pub apps: Vec<String>,
+    pub details: Vec<AppLinkDetails>,
+}
+
+#[derive(Serialize)]
+pub struct AppLinkDetails {
+    #[serde(rename = "appID")]
+    pub app_id: String,
+    #[serde(rename = "appIDs")]
+    pub app_ids: Vec<String>,
+    pub paths: Vec<String>,
+    pub components: Vec<AppLinkComponent>,
+}
+
+#[derive(Serialize)]
+pub struct AppLinkComponent {
+    #[serde(rename = "/")]
+    pub path: String,
+}
+
+/// An entry of `/.well-known/assetlinks.json`.
+#[derive(Serialize)]
+pub struct AssetLink {
+    pub relation: Vec<&'static str>,
+    pub target: AssetLinkTarget,
+}
+
+#[derive(Serialize)]
+pub struct AssetLinkTarget {
+    pub namespace: &'static str,
+    pub package_name: String,
+    pub sha256_cert_fingerprints: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct QrCodeRequest {
+    pub format: Option<QrFormat>,
+    pub size: Option<u32>,
+    pub error_correction: Option<QrErrorCorrection>,
+    pub foreground: Option<String>,
+    pub background: Option<String>,
+    pub margin: Option<u32>,
+    /// URL of a PNG image shown in the middle of the code.
+    pub logo: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum QrFormat {
+    Png,
+    Svg,
+}
+
+/// Share of the code that can be damaged or covered and still scan, from about 7% for `low` to 30% for `high`.
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum QrErrorCorrection {
+    Low,
+    Medium,
+    Quartile,
+    High,
+}
+
+#[derive(Deserialize)]
+pub struct PixelHost {
+    pub host: String,
+}
+
+#[derive(Serialize)]
+pub struct PixelHostsResponse {
+    pub hosts: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct BlocklistMatch {
+    pub key: String,
+    pub domain: Option<String>,
+    pub url: String,
+    pub user_id: i64,
+    pub entry: String,
+}
+
+#[derive(Serialize)]
+pub struct BlocklistMatchesResponse {
+    pub matches: Vec<BlocklistMatch>,
+}
+
+#[derive(Serialize)]
+pub struct ErrorResponse {
+    pub error: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<&'static str>,
+}
+
+impl ErrorResponse {
+    pub fn new(error: &'static str, message: impl Into<String>) -> Self {
+        ErrorResponse {
+            error,
+            message: message.into(),
+            field: None,
+        }
+    }
+
+    pub fn with_field(mut self, field: &'static str) -> Self {
+        self.field = Some(field);
+        self
+    }
 }
@@ -0,0 +1,408 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::http::HeaderMap;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use rusqlite::{Connection, OptionalExtension};
+use time::OffsetDateTime;
+use url::{Host, Url};
+
+use crate::headers::TypedHeaderValues;
+
+// Link preview crawlers of chat apps and social networks, matched case-insensitively against the User-Agent
+const UNFURL_BOTS: &[&str] = &[
+    "slackbot",
+    "facebookexternalhit",
+    "facebot",
+    "twitterbot",
+    "discordbot",
+    "telegrambot",
+    "whatsapp",
+    "linkedinbot",
+    "skypeuripreview",
+    "microsoftpreview",
+    "redditbot",
+    "mastodon",
+    "pinterestbot",
+    "embedly",
+    "iframely",
+    "vkshare",
+];
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_REDIRECTS: usize = 3;
+const MAX_DOCUMENT_SIZE: usize = 512 * 1024;
+const CACHE_TTL: time::Duration = time::Duration::days(1);
+
+#[derive(Debug, Default, Clone)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<String>,
+}
+
+impl Metadata {
+    pub fn is_complete(&self) -> bool {
+        self.title.is_some() && self.description.is_some() && self.image.is_some()
+    }
+
+    /// Fills the fields missing in `self` from `other`.
+    pub fn or(self, other: Metadata) -> Metadata {
+        Metadata {
+            title: self.title.or(other.title),
+            description: self.description.or(other.description),
+            image: self.image.or(other.image),
+        }
+    }
+}
+
+pub fn is_bot(headers: &HeaderMap) -> bool {
+    headers.string("user-agent").is_some_and(|user_agent| {
+        let user_agent = user_agent.to_ascii_lowercase();
+        UNFURL_BOTS.iter().any(|bot| user_agent.contains(bot))
+    })
+}
+
+/// Resolves hostnames for the fetcher and drops every address that isn't public. Connections only go to the
+/// addresses returned here, so a hostname can't be pointed at an internal service after it was checked.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect();
+
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Client for fetching urls that visitors or link owners control. Hostnames only resolve to public addresses and
+/// every redirect is checked like the url it started from.
+pub fn client() -> reqwest::Client {
+    let redirect_policy = reqwest::redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() > MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if !is_public_url(attempt.url().as_str()) {
+            attempt.stop()
+        } else {
+            attempt.follow()
+        }
+    });
+
+    reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .redirect(redirect_policy)
+        .dns_resolver(Arc::new(PublicResolver))
+        // A proxy would resolve the hostname itself
+        .no_proxy()
+        .user_agent(concat!("url-shortener/", env!("CARGO_PKG_VERSION"), " (link preview)"))
+        .build()
+        .expect("HTTP client can be built")
+}
+
+pub fn find_cached(
+    connection: &mut Connection,
+    url: &str,
+    now: OffsetDateTime,
+) -> Result<Option<Metadata>, rusqlite::Error> {
+    let mut query = connection
+        .prepare_cached("SELECT title, description, image FROM url_metadata WHERE url = ?1 AND fetched_at > ?2")?;
+
+    query
+        .query_row((url, (now - CACHE_TTL).unix_timestamp()), |row| {
+            Ok(Metadata {
+                title: row.get("title")?,
+                description: row.get("description")?,
+                image: row.get("image")?,
+            })
+        })
+        .optional()
+}
+
+pub fn store(connection: &mut Connection, url: &str, metadata: &Metadata) -> Result<(), rusqlite::Error> {
+    connection
+        .prepare_cached(
+            r"INSERT INTO url_metadata (url, title, description, image, fetched_at) VALUES (?1, ?2, ?3, ?4, unixepoch())
+              ON CONFLICT (url) DO UPDATE SET
+                title = excluded.title,
+                description = excluded.description,
+                image = excluded.image,
+                fetched_at = excluded.fetched_at",
+        )?
+        .execute((url, &metadata.title, &metadata.description, &metadata.image))
+        .map(|_| ())
+}
+
+/// Reads the Open Graph tags of `url`, failures are cached as empty metadata by the caller so they aren't retried
+/// on every unfurl.
+pub async fn fetch(client: &reqwest::Client, url: &str) -> Metadata {
+    if !is_public_url(url) {
+        return Metadata::default();
+    }
+
+    let Ok(mut response) = client.get(url).send().await else {
+        return Metadata::default();
+    };
+
+    let is_html = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/html"));
+
+    if !response.status().is_success() || !is_html {
+        return Metadata::default();
+    }
+
+    let mut document = Vec::new();
+
+    while document.len() < MAX_DOCUMENT_SIZE
+        && let Ok(Some(chunk)) = response.chunk().await
+    {
+        document.extend_from_slice(&chunk);
+    }
+
+    let mut metadata = parse(&String::from_utf8_lossy(&document));
+
+    // Images are often given relative to the page
+    metadata.image = metadata
+        .image
+        .and_then(|image| response.url().join(&image).ok())
+        .map(String::from);
+
+    metadata
+}
+
+/// Keeps the fetcher away from internal services. Only the scheme and IP literals can be checked here, hostnames are
+/// checked against their addresses by the resolver of [`client`].
+pub fn is_public_url(url: &str) -> bool {
+    let Ok(url) = Url::parse(url) else {
+        return false;
+    };
+
+    if !matches!(url.scheme(), "http" | "https") {
+        return false;
+    }
+
+    match url.host() {
+        Some(Host::Domain(domain)) => domain != "localhost" && !domain.ends_with(".localhost"),
+        Some(Host::Ipv4(ip)) => is_public_ip(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => is_public_ip(IpAddr::V6(ip)),
+        None => false,
+    }
+}
+
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => is_public_ipv6(ip),
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [first, second, third, _] = ip.octets();
+
+    let is_reserved = first == 0
+        // Carrier-grade NAT
+        || (first == 100 && (second & 0xc0) == 64)
+        || (first == 192 && second == 0 && third == 0)
+        || (first == 198 && (second & 0xfe) == 18)
+        || first >= 240;
+
+    !(is_reserved
+        || ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation())
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+
+    // IPv4-mapped and -compatible addresses reach IPv4 hosts, as does NAT64
+    let embeds_ipv4 = segments[..5] == [0; 5] && (segments[5] == 0 || segments[5] == 0xffff);
+    let is_nat64 = segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0];
+
+    !(embeds_ipv4
+        || is_nat64
+        || ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local
+        || (segments[0] & 0xfe00) == 0xfc00
+        // Link-local and the deprecated site-local
+        || (segments[0] & 0xff80) == 0xfe80
+        // Documentation
+        || segments[..2] == [0x2001, 0xdb8])
+}
+
+fn parse(document: &str) -> Metadata {
+    let mut metadata = Metadata::default();
+    let lowercase = document.to_ascii_lowercase();
+
+    for (start, _) in lowercase.match_indices("<meta") {
+        let Some(end) = lowercase[start..].find('>') else {
+            break;
+        };
+        let tag = &document[start..start + end];
+
+        let property = attribute(tag, "property")
+            .or_else(|| attribute(tag, "name"))
+            .map(|property| property.to_ascii_lowercase());
+        let content = attribute(tag, "content").filter(|content| !content.is_empty());
+
+        match (property.as_deref(), content) {
+            (Some("og:title"), Some(content)) => metadata.title = metadata.title.or(Some(content)),
+            (Some("og:description" | "description"), Some(content)) => {
+                metadata.description = metadata.description.or(Some(content))
+            }
+            (Some("og:image"), Some(content)) => metadata.image = metadata.image.or(Some(content)),
+            _ => {}
+        }
+    }
+
+    if let Some(start) = lowercase.find("<title")
+        && let Some(open) = lowercase[start..].find('>')
+        && let Some(close) = lowercase[start..].find("</title")
+        && open < close
+    {
+        let title = unescape(document[start + open + 1..start + close].trim());
+        metadata.title = metadata.title.or((!title.is_empty()).then_some(title));
+    }
+
+    metadata
+}
+
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lowercase = tag.to_ascii_lowercase();
+    let mut offset = 0;
+
+    while let Some(index) = lowercase[offset..].find(name) {
+        let start = offset + index;
+        offset = start + name.len();
+
+        // Must be a whole attribute name, not the end of another one
+        let preceded_by_space = lowercase[..start].ends_with(|c: char| c.is_ascii_whitespace());
+        let rest = lowercase[offset..].trim_start();
+
+        if !preceded_by_space || !rest.starts_with('=') {
+            continue;
+        }
+
+        let value_start = tag.len() - rest.len() + 1;
+        let value = tag[value_start..].trim_start();
+
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => value[1..].split(quote).next().unwrap_or_default(),
+            _ => value
+                .split(|c: char| c.is_ascii_whitespace())
+                .next()
+                .unwrap_or_default(),
+        };
+
+        return Some(unescape(value));
+    }
+
+    None
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        let addresses = [
+            "0.0.0.0",
+            "10.1.2.3",
+            "100.64.0.1",
+            "100.127.255.254",
+            "127.0.0.1",
+            "169.254.169.254",
+            "172.16.0.1",
+            "192.168.1.1",
+            "224.0.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "::127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "fc00::1",
+            "fd12:3456::1",
+            "fe80::1",
+            "febf::1",
+            "ff02::1",
+        ];
+
+        for address in addresses {
+            assert!(!is_public_ip(address.parse().unwrap()), "{address} is internal");
+        }
+    }
+
+    #[test]
+    fn internet_addresses_are_public() {
+        for address in [
+            "1.1.1.1",
+            "93.184.216.34",
+            "100.128.0.1",
+            "2606:4700:4700::1111",
+            "2a00:1450::1",
+        ] {
+            assert!(is_public_ip(address.parse().unwrap()), "{address} is public");
+        }
+    }
+
+    #[test]
+    fn only_public_web_urls_are_fetched() {
+        assert!(is_public_url("https://example.com/page"));
+        assert!(is_public_url("http://1.1.1.1/"));
+
+        for url in [
+            "ftp://example.com/",
+            "file:///etc/passwd",
+            "http://localhost:8080/",
+            "http://admin.localhost/",
+            "http://127.0.0.1/",
+            "http://2130706433/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://[::1]/",
+            "http://[::ffff:10.0.0.1]/",
+            "not a url",
+        ] {
+            assert!(!is_public_url(url), "{url} must not be fetched");
+        }
+    }
+
+    #[tokio::test]
+    async fn hostnames_of_internal_addresses_are_not_resolved() {
+        let name: Name = "localhost".parse().unwrap();
+
+        assert!(PublicResolver.resolve(name).await.is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use std::fmt;
+
+use url::Url;
+
+const MAX_URL_LENGTH: usize = 2048;
+const DEFAULT_ALLOWED_SCHEMES: &str = "http,https";
+
+#[derive(Debug)]
+pub enum UrlError {
+    TooLong,
+    Malformed(url::ParseError),
+    SchemeNotAllowed(String),
+    MissingHost,
+}
+
+impl UrlError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            UrlError::TooLong => "url_too_long",
+            UrlError::Malformed(_) => "url_malformed",
+            UrlError::SchemeNotAllowed(_) => "url_scheme_not_allowed",
+            UrlError::MissingHost => "url_missing_host",
+        }
+    }
+}
+
+impl fmt::Display for UrlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UrlError::TooLong => write!(f, "url must not be longer than {MAX_URL_LENGTH} characters"),
+            UrlError::Malformed(err) => write!(f, "url is not a valid absolute url: {err}"),
+            UrlError::SchemeNotAllowed(scheme) => write!(f, "url scheme '{scheme}' is not allowed"),
+            UrlError::MissingHost => write!(f, "url must contain a host"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct UrlPolicy {
+    allowed_schemes: Vec<String>,
+}
+
+impl UrlPolicy {
+    /// Reads a comma separated scheme allowlist from `ALLOWED_URL_SCHEMES`, defaulting to http and https.
+    pub fn from_env() -> Self {
+        let schemes = std::env::var("ALLOWED_URL_SCHEMES").unwrap_or(DEFAULT_ALLOWED_SCHEMES.to_owned());
+
+        UrlPolicy {
+            allowed_schemes: schemes
+                .split(',')
+                .map(|scheme| scheme.trim().to_ascii_lowercase())
+                .filter(|scheme| !scheme.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Validates a destination and returns its canonical form: lowercase scheme and host, IDN hosts as punycode,
+    /// default ports removed and an explicit root path. Two urls that normalize to the same string are duplicates.
+    pub fn normalize(&self, input: &str) -> Result<String, UrlError> {
+        let input = input.trim();
+
+        if input.len() > MAX_URL_LENGTH {
+            return Err(UrlError::TooLong);
+        }
+
+        let url = Url::parse(input).map_err(UrlError::Malformed)?;
+
+        if !self.allowed_schemes.iter().any(|scheme| scheme == url.scheme()) {
+            return Err(UrlError::SchemeNotAllowed(url.scheme().to_owned()));
+        }
+
+        if url.host_str().is_none_or(str::is_empty) {
+            return Err(UrlError::MissingHost);
+        }
+
+        // Punycode and percent-encoding can make the normalized form longer than the input
+        if url.as_str().len() > MAX_URL_LENGTH {
+            return Err(UrlError::TooLong);
+        }
+
+        Ok(url.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(schemes: &[&str]) -> UrlPolicy {
+        UrlPolicy {
+            allowed_schemes: schemes.iter().map(|scheme| scheme.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn urls_are_normalized_to_their_canonical_form() {
+        let policy = policy(&["http", "https"]);
+
+        assert_eq!(
+            policy.normalize(" HTTPS://Example.COM ").unwrap(),
+            "https://example.com/"
+        );
+        assert_eq!(
+            policy.normalize("http://example.com:80/a?b#c").unwrap(),
+            "http://example.com/a?b#c"
+        );
+        assert_eq!(
+            policy.normalize("https://example.com:8443").unwrap(),
+            "https://example.com:8443/"
+        );
+        assert_eq!(
+            policy.normalize("https://bücher.example/straße").unwrap(),
+            "https://xn--bcher-kva.example/stra%C3%9Fe"
+        );
+    }
+
+    #[test]
+    fn invalid_urls_are_refused() {
+        let policy = policy(&["https", "mailto"]);
+
+        assert!(matches!(policy.normalize("example.com"), Err(UrlError::Malformed(_))));
+        assert!(
+            matches!(policy.normalize("http://example.com"), Err(UrlError::SchemeNotAllowed(scheme)) if scheme == "http")
+        );
+        assert!(matches!(
+            policy.normalize("javascript:alert(1)"),
+            Err(UrlError::SchemeNotAllowed(_))
+        ));
+        assert!(matches!(
+            policy.normalize("mailto:someone@example.com"),
+            Err(UrlError::MissingHost)
+        ));
+        assert!(matches!(
+            policy.normalize(&format!("https://example.com/{}", "a".repeat(MAX_URL_LENGTH))),
+            Err(UrlError::TooLong)
+        ));
+    }
+}
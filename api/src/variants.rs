@@ -0,0 +1,34 @@
+use crate::entities::UrlVariant;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Picks a variant according to the weights. The choice is derived from the visitor id, so returning
+/// visitors see the same variant as long as the variants of the link don't change.
+pub fn pick<'a>(variants: &'a [UrlVariant], key: &str, visitor_id: &str) -> Option<&'a UrlVariant> {
+    let total: u64 = variants.iter().map(|variant| u64::from(variant.weight)).sum();
+
+    if total == 0 {
+        return None;
+    }
+
+    let mut point = fnv1a(format!("{key}:{visitor_id}").as_bytes()) % total;
+
+    variants.iter().find(|variant| {
+        let weight = u64::from(variant.weight);
+
+        if point < weight {
+            return true;
+        }
+
+        point -= weight;
+        false
+    })
+}
+
+// A hash that is stable across restarts and compiler versions, unlike std's DefaultHasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+    })
+}